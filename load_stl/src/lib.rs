@@ -2,26 +2,59 @@ extern crate proc_macro;
 
 use std::ops::Index;
 
-use proc_macro::TokenStream;
+use proc_macro::{TokenStream, TokenTree};
 
+/// Embeds an STL file as a `Geometry` with face normals, `embed_stl!("model.stl")`.
+///
+/// `embed_stl!("model.stl", crease = 40)` also generates smooth vertex normals, keeping a hard
+/// edge where faces meet at more than the crease angle in degrees.
+///
+/// Binary files with colors in their attribute bytes get one face color per face. The format
+/// can't mark part of a mesh as uncolored, faces without a color are white rather than
+/// `K3dMesh::color`.
 #[proc_macro]
 pub fn embed_stl(input: TokenStream) -> TokenStream {
-    let file_path = input.to_string();
+    let mut tokens = input.into_iter();
 
-    let r = load_stl(file_path.trim_matches('"'));
+    let file_path = match tokens.next() {
+        Some(TokenTree::Literal(path)) => path.to_string(),
+        _ => panic!("expected the path of an STL file"),
+    };
+
+    let crease_angle = match (tokens.next(), tokens.next(), tokens.next(), tokens.next()) {
+        (None, ..) => None,
+        (Some(TokenTree::Punct(comma)), None, ..) if comma.as_char() == ',' => None,
+        (
+            Some(TokenTree::Punct(comma)),
+            Some(TokenTree::Ident(name)),
+            Some(TokenTree::Punct(equals)),
+            Some(TokenTree::Literal(degrees)),
+        ) if comma.as_char() == ',' && name.to_string() == "crease" && equals.as_char() == '=' => {
+            let degrees: f32 = degrees
+                .to_string()
+                .parse()
+                .expect("expected the crease angle in degrees");
+            Some(degrees.to_radians())
+        }
+        _ => panic!("expected `crease = <degrees>` after the path"),
+    };
+    match (tokens.next(), tokens.next()) {
+        (None, _) => {}
+        (Some(TokenTree::Punct(comma)), None) if comma.as_char() == ',' => {}
+        _ => panic!("unexpected tokens after the crease angle"),
+    }
+
+    let r = load_stl(file_path.trim_matches('"'), crease_angle);
 
     r.parse().unwrap()
 }
 
-fn load_stl(file_name: &str) -> String {
-    let mut file = std::fs::OpenOptions::new()
-        .read(true)
-        .open(file_name)
-        .unwrap();
-    let stl = stl_io::read_stl(&mut file).unwrap();
+fn load_stl(file_name: &str, crease_angle: Option<f32>) -> String {
+    let bytes = std::fs::read(file_name).unwrap();
+    let stl = stl_io::read_stl(&mut std::io::Cursor::new(&bytes)).unwrap();
 
     let mut vertices = String::new();
-    for vertex in stl.vertices {
+    for vertex in &stl.vertices {
         vertices += &format!(
             "[{}f32,{}f32,{}f32],",
             vertex.index(0),
@@ -38,22 +71,61 @@ fn load_stl(file_name: &str) -> String {
         );
     }
 
+    let face_indices = stl
+        .faces
+        .iter()
+        .map(|f| [f.vertices[0], f.vertices[1], f.vertices[2]])
+        .collect::<Vec<_>>();
+    let vertex_positions = stl
+        .vertices
+        .iter()
+        .map(|v| [v[0], v[1], v[2]])
+        .collect::<Vec<_>>();
+
+    // the normals stored in STL files are often zero or wrong, the winding order is reliable
+    let mut face_normals = vec![[0.0; 3]; face_indices.len()];
+    embedded_gfx::mesh::Geometry::compute_face_normals(
+        &vertex_positions,
+        &face_indices,
+        &mut face_normals,
+    )
+    .unwrap();
+
     let mut normals = String::new();
-    for triangle in &stl.faces {
-        normals += &format!(
-            "[{}f32,{}f32,{}f32],",
-            triangle.normal.index(0),
-            triangle.normal.index(1),
-            triangle.normal.index(2)
-        );
+    for normal in &face_normals {
+        normals += &format!("[{}f32,{}f32,{}f32],", normal[0], normal[1], normal[2]);
     }
 
-    let lines = embedded_gfx::mesh::Geometry::lines_from_faces(
-        &stl.faces
-            .iter()
-            .map(|f| [f.vertices[0], f.vertices[1], f.vertices[2]])
-            .collect::<Vec<_>>(),
-    );
+    let mut vertex_normals = String::new();
+    if let Some(crease_angle) = crease_angle {
+        let mut adjacency = vec![0; face_indices.len() * 3];
+        let mut corner_normals = vec![[[0.0; 3]; 3]; face_indices.len()];
+        embedded_gfx::mesh::Geometry::compute_vertex_normals(
+            &vertex_positions,
+            &face_indices,
+            crease_angle,
+            &mut adjacency,
+            &mut corner_normals,
+        )
+        .unwrap();
+
+        for corners in &corner_normals {
+            vertex_normals += "[";
+            for normal in corners {
+                vertex_normals += &format!("[{}f32,{}f32,{}f32],", normal[0], normal[1], normal[2]);
+            }
+            vertex_normals += "],";
+        }
+    }
+
+    let mut face_colors = String::new();
+    if let Some(colors) = face_colors_from_attributes(&bytes, face_indices.len()) {
+        for [r, g, b] in colors {
+            face_colors += &format!("embedded_gfx::mesh::Rgb565::new({r},{g},{b}),");
+        }
+    }
+
+    let lines = embedded_gfx::mesh::Geometry::lines_from_faces(&face_indices);
 
     let mut lines_ = String::new();
     for line in lines {
@@ -71,14 +143,108 @@ fn load_stl(file_name: &str) -> String {
             {faces}
         ],
         colors: &[],
+        face_colors: &[
+            {face_colors}
+        ],
         lines: &[
             {lines_}
         ],
         normals: &[
             {normals}
         ],
+        vertex_normals: &[
+            {vertex_normals}
+        ],
     }}"
     );
 
     ret
 }
+
+/// Rgb565 components of each face of a binary STL file, from the VisCAM/SolidView convention
+/// for the attribute bytes: bit 15 set when the color is valid, then 5 bits of red, green and blue.
+/// Returns `None` if no face has a color, faces without one are white since `Geometry::face_colors`
+/// is either empty or has a color for every face.
+fn face_colors_from_attributes(bytes: &[u8], face_count: usize) -> Option<Vec<[u8; 3]>> {
+    const HEADER: usize = 84;
+    const TRIANGLE: usize = 50;
+
+    // ASCII files don't have the exact size of a binary file
+    if bytes.len() != HEADER + face_count * TRIANGLE {
+        return None;
+    }
+
+    let attributes = bytes[HEADER..]
+        .chunks_exact(TRIANGLE)
+        .map(|triangle| u16::from_le_bytes([triangle[48], triangle[49]]))
+        .collect::<Vec<_>>();
+
+    if attributes.iter().all(|a| a & 0x8000 == 0) {
+        return None;
+    }
+
+    let colors = attributes
+        .iter()
+        .map(|a| {
+            if a & 0x8000 == 0 {
+                return [31, 63, 31];
+            }
+
+            let (r, g, b) = ((a >> 10) & 0x1f, (a >> 5) & 0x1f, a & 0x1f);
+            // green has 6 bits in Rgb565
+            [r as u8, (g << 1 | g >> 4) as u8, b as u8]
+        })
+        .collect();
+
+    Some(colors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A binary STL file with zeroed geometry and the given attribute words.
+    fn binary_stl(attributes: &[u16]) -> Vec<u8> {
+        let mut bytes = vec![0; 80];
+        bytes.extend((attributes.len() as u32).to_le_bytes());
+        for attribute in attributes {
+            bytes.extend([0; 48]);
+            bytes.extend(attribute.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn valid_bit_selects_the_color() {
+        let bytes = binary_stl(&[0x8000 | (31 << 10), 31 << 10]);
+
+        assert_eq!(
+            face_colors_from_attributes(&bytes, 2),
+            Some(vec![[31, 0, 0], [31, 63, 31]])
+        );
+    }
+
+    #[test]
+    fn channels_are_five_bits_with_green_widened_to_six() {
+        let bytes = binary_stl(&[
+            0x8000 | (3 << 10) | (16 << 5) | 7,
+            0x8000 | (31 << 5),
+            0x8000 | (1 << 5) | 31,
+            0x8000,
+        ]);
+
+        assert_eq!(
+            face_colors_from_attributes(&bytes, 4),
+            Some(vec![[3, 33, 7], [0, 63, 0], [0, 2, 31], [0, 0, 0]])
+        );
+    }
+
+    #[test]
+    fn uncolored_files_have_no_face_colors() {
+        let bytes = binary_stl(&[0, 0x7fff]);
+
+        assert_eq!(face_colors_from_attributes(&bytes, 2), None);
+        // not the size of a binary file with that many faces, e.g. ASCII
+        assert_eq!(face_colors_from_attributes(&bytes, 3), None);
+    }
+}
@@ -0,0 +1,130 @@
+use embedded_graphics_core::{
+    Pixel,
+    draw_target::DrawTarget,
+    geometry::{OriginDimensions, Point, Size},
+    pixelcolor::Rgb565,
+    primitives::Rectangle,
+};
+
+use crate::DrawPrimitive;
+use crate::SUBPIXEL_BITS;
+use crate::blend::{Blend, BlendTarget};
+
+/// A primitive along with the range of rows it covers, so that it is only drawn into the bands
+/// it overlaps.
+#[derive(Debug, Clone, Copy)]
+pub struct BinnedPrimitive {
+    pub primitive: DrawPrimitive,
+    pub min_y: i32,
+    pub max_y: i32,
+}
+
+impl BinnedPrimitive {
+    pub fn new(primitive: DrawPrimitive) -> BinnedPrimitive {
+        let (min_y, max_y) = match &primitive {
+            DrawPrimitive::ColoredPoint(p, _) => (p.y, p.y),
+            DrawPrimitive::Line([p1, p2], ..) | DrawPrimitive::BlendedLine([p1, p2], ..) => {
+                (p1.y.min(p2.y), p1.y.max(p2.y))
+            }
+            // the coverage spills one pixel below the line
+            DrawPrimitive::AntialiasedLine([p1, p2], ..) => (p1.y.min(p2.y), p1.y.max(p2.y) + 1),
+            DrawPrimitive::ThickLine([p1, p2], _, width, _) => {
+                let radius = *width as i32 / 2 + 1;
+                (p1.y.min(p2.y) - radius, p1.y.max(p2.y) + radius)
+            }
+            DrawPrimitive::PointSprite(p, _, size, ..) => {
+                (p.y - *size as i32 / 2, p.y + *size as i32 / 2)
+            }
+            DrawPrimitive::ColoredTriangle([p1, p2, p3], ..)
+            | DrawPrimitive::BlendedTriangle([p1, p2, p3], ..)
+            | DrawPrimitive::ShadedTriangle([p1, p2, p3], ..) => (
+                p1.y.min(p2.y).min(p3.y) >> SUBPIXEL_BITS,
+                p1.y.max(p2.y).max(p3.y) >> SUBPIXEL_BITS,
+            ),
+        };
+
+        BinnedPrimitive {
+            primitive,
+            min_y,
+            max_y,
+        }
+    }
+
+    pub fn overlaps_rows(&self, top: i32, height: i32) -> bool {
+        self.max_y >= top && self.min_y < top + height
+    }
+}
+
+/// Draws into a target holding only the rows `y_offset..y_offset + target height` of a
+/// `screen_size` screen, pixels outside of the band are discarded.
+pub struct BandTarget<'t, D> {
+    target: &'t mut D,
+    y_offset: i32,
+    screen_size: Size,
+}
+
+impl<'t, D: DrawTarget> BandTarget<'t, D> {
+    pub fn new(target: &'t mut D, y_offset: i32, screen_size: Size) -> BandTarget<'t, D> {
+        BandTarget {
+            target,
+            y_offset,
+            screen_size,
+        }
+    }
+
+    fn band_area(&self) -> Rectangle {
+        let band = self.target.bounding_box();
+
+        Rectangle::new(
+            Point::new(band.top_left.x, band.top_left.y + self.y_offset),
+            band.size,
+        )
+    }
+}
+
+impl<D: DrawTarget> DrawTarget for BandTarget<'_, D> {
+    type Color = D::Color;
+    type Error = D::Error;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        let area = self.band_area();
+        let offset = Point::new(0, self.y_offset);
+
+        self.target.draw_iter(
+            pixels
+                .into_iter()
+                .filter(|Pixel(p, _)| area.contains(*p))
+                .map(|Pixel(p, c)| Pixel(p - offset, c)),
+        )
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        let area = area.intersection(&self.band_area());
+        if area.is_zero_sized() {
+            return Ok(());
+        }
+
+        self.target.fill_solid(
+            &Rectangle::new(area.top_left - Point::new(0, self.y_offset), area.size),
+            color,
+        )
+    }
+}
+
+impl<D: BlendTarget> BlendTarget for BandTarget<'_, D> {
+    fn blend_pixel(&mut self, point: Point, color: Rgb565, blend: Blend) {
+        if self.band_area().contains(point) {
+            self.target
+                .blend_pixel(point - Point::new(0, self.y_offset), color, blend);
+        }
+    }
+}
+
+impl<D> OriginDimensions for BandTarget<'_, D> {
+    fn size(&self) -> Size {
+        self.screen_size
+    }
+}
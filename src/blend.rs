@@ -0,0 +1,64 @@
+use embedded_graphics_core::{
+    draw_target::DrawTarget,
+    geometry::Point,
+    pixelcolor::{Rgb565, RgbColor},
+};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BlendMode {
+    #[default]
+    Normal,
+    Additive,
+    Multiply,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Blend {
+    pub alpha: u8,
+    pub mode: BlendMode,
+}
+
+impl Blend {
+    pub fn new(alpha: u8, mode: BlendMode) -> Blend {
+        Blend { alpha, mode }
+    }
+
+    pub fn is_opaque(&self) -> bool {
+        self.alpha == u8::MAX && self.mode == BlendMode::Normal
+    }
+
+    /// Combines `src` over `dst`, the blend mode result is then mixed with `dst` by `alpha`.
+    pub fn apply(&self, src: Rgb565, dst: Rgb565) -> Rgb565 {
+        let alpha = self.alpha as u32;
+
+        let channel = |s: u8, d: u8, max: u8| -> u8 {
+            let (s, d, max) = (s as u32, d as u32, max as u32);
+
+            let blended = match self.mode {
+                BlendMode::Normal => s,
+                BlendMode::Additive => (d + s).min(max),
+                BlendMode::Multiply => d * s / max,
+            };
+
+            ((blended * alpha + d * (255 - alpha) + 127) / 255) as u8
+        };
+
+        Rgb565::new(
+            channel(src.r(), dst.r(), Rgb565::MAX_R),
+            channel(src.g(), dst.g(), Rgb565::MAX_G),
+            channel(src.b(), dst.b(), Rgb565::MAX_B),
+        )
+    }
+}
+
+/// A draw target that can read back what it contains, needed for blending.
+pub trait BlendTarget: DrawTarget<Color = Rgb565> {
+    fn blend_pixel(&mut self, point: Point, color: Rgb565, blend: Blend);
+
+    /// Blends the pixels from `x_start` to `x_end` included on row `y`.
+    fn blend_span(&mut self, y: i32, x_start: i32, x_end: i32, color: Rgb565, blend: Blend) {
+        for x in x_start..=x_end {
+            self.blend_pixel(Point::new(x, y), color, blend);
+        }
+    }
+}
@@ -1,6 +1,53 @@
 use core::f32::consts;
 
-use nalgebra::{Isometry3, Perspective3, Point3, Vector3};
+use nalgebra::{Isometry3, Perspective3, Point3, Vector3, Vector4};
+
+use crate::mesh::BoundingSphere;
+use crate::picking::Ray;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Plane {
+    pub normal: Vector3<f32>,
+    pub d: f32,
+}
+
+impl Plane {
+    fn from_coefficients(coefficients: Vector4<f32>) -> Plane {
+        let normal = coefficients.xyz();
+        let length = normal.norm();
+
+        Plane {
+            normal: normal / length,
+            d: coefficients.w / length,
+        }
+    }
+
+    /// Signed distance, positive on the side the normal points to.
+    pub fn distance(&self, point: &Point3<f32>) -> f32 {
+        self.normal.dot(&point.coords) + self.d
+    }
+}
+
+/// Planes are ordered left, right, bottom, top, near, far, with normals pointing inwards.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frustum {
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.distance(&sphere.center) >= -sphere.radius)
+    }
+
+    /// Conservative test, the points are considered outside only if they all lie behind the same plane.
+    pub fn intersects_points(&self, points: &[Point3<f32>]) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| points.iter().any(|p| plane.distance(p) >= 0.0))
+    }
+}
 
 pub struct Camera {
     pub position: Point3<f32>,
@@ -10,6 +57,7 @@ pub struct Camera {
     view_matrix: nalgebra::Matrix4<f32>,
     projection_matrix: nalgebra::Matrix4<f32>,
     pub vp_matrix: nalgebra::Matrix4<f32>,
+    inverse_vp_matrix: nalgebra::Matrix4<f32>,
     target: Point3<f32>,
     aspect_ratio: f32,
 }
@@ -22,6 +70,7 @@ impl Camera {
             view_matrix: nalgebra::Matrix4::identity(),
             projection_matrix: nalgebra::Matrix4::identity(),
             vp_matrix: nalgebra::Matrix4::identity(),
+            inverse_vp_matrix: nalgebra::Matrix4::identity(),
             target: Point3::new(0.0, 0.0, 0.0),
             aspect_ratio,
             near: 0.4,
@@ -45,6 +94,12 @@ impl Camera {
         self.update_projection();
     }
 
+    pub fn set_aspect_ratio(&mut self, aspect_ratio: f32) {
+        self.aspect_ratio = aspect_ratio;
+
+        self.update_projection();
+    }
+
     pub fn set_target(&mut self, target: Point3<f32>) {
         self.target = target;
         self.update_view();
@@ -56,16 +111,52 @@ impl Camera {
         Vector3::new(transpose[(2, 0)], transpose[(2, 1)], transpose[(2, 2)])
     }
 
+    pub fn frustum(&self) -> Frustum {
+        let row = |i: usize| self.vp_matrix.row(i).transpose();
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+
+        Frustum {
+            planes: [
+                Plane::from_coefficients(w + x),
+                Plane::from_coefficients(w - x),
+                Plane::from_coefficients(w + y),
+                Plane::from_coefficients(w - y),
+                Plane::from_coefficients(w + z),
+                Plane::from_coefficients(w - z),
+            ],
+        }
+    }
+
+    /// Returns the world-space ray going through the given point in normalized device
+    /// coordinates, starting on the near plane.
+    pub fn unproject(&self, ndc_x: f32, ndc_y: f32) -> Ray {
+        let near = self.inverse_vp_matrix * Vector4::new(ndc_x, ndc_y, -1.0, 1.0);
+        let far = self.inverse_vp_matrix * Vector4::new(ndc_x, ndc_y, 1.0, 1.0);
+
+        let near = Point3::from_homogeneous(near).unwrap_or(self.position);
+        let far = Point3::from_homogeneous(far).unwrap_or(self.target);
+
+        Ray::new(near, far - near)
+    }
+
     fn update_view(&mut self) {
         let view = Isometry3::look_at_rh(&self.position, &self.target, &Vector3::y());
 
         self.view_matrix = view.to_homogeneous();
-        self.vp_matrix = self.projection_matrix * self.view_matrix;
+        self.update_vp_matrix();
     }
 
     fn update_projection(&mut self) {
         let projection = Perspective3::new(self.aspect_ratio, self.fov, self.near, self.far);
         self.projection_matrix = projection.to_homogeneous();
+        self.update_vp_matrix();
+    }
+
+    fn update_vp_matrix(&mut self) {
         self.vp_matrix = self.projection_matrix * self.view_matrix;
+        self.inverse_vp_matrix = self
+            .vp_matrix
+            .try_inverse()
+            .unwrap_or(nalgebra::Matrix4::identity());
     }
 }
@@ -0,0 +1,93 @@
+use core::cmp::Reverse;
+
+use heapless::Vec;
+
+use crate::DrawPrimitive;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DepthSortKey {
+    #[default]
+    Average,
+    Maximum,
+}
+
+impl DepthSortKey {
+    pub fn depth(&self, depths: &[i32]) -> i32 {
+        match self {
+            DepthSortKey::Average => {
+                let sum: i64 = depths.iter().map(|&d| d as i64).sum();
+                (sum / depths.len().max(1) as i64) as i32
+            }
+            DepthSortKey::Maximum => depths.iter().copied().max().unwrap_or(0),
+        }
+    }
+}
+
+/// What to do when a primitive does not fit in the sort buffer.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DepthSortOverflow {
+    /// Discard the primitive that does not fit.
+    #[default]
+    DropNew,
+    /// Keep the nearest primitives, discarding the farthest one.
+    DropFarthest,
+    /// Sort and emit what has been collected so far, then start a new batch.
+    /// Ordering is only correct within each batch.
+    Flush,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DepthSortSettings {
+    pub key: DepthSortKey,
+    pub overflow: DepthSortOverflow,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DepthSortedPrimitive {
+    pub depth: i32,
+    pub primitive: DrawPrimitive,
+}
+
+/// Returns false if a primitive had to be dropped.
+pub(crate) fn push<F, const N: usize>(
+    buffer: &mut Vec<DepthSortedPrimitive, N>,
+    entry: DepthSortedPrimitive,
+    overflow: DepthSortOverflow,
+    callback: &mut F,
+) -> bool
+where
+    F: FnMut(DrawPrimitive),
+{
+    let Err(entry) = buffer.push(entry) else {
+        return true;
+    };
+
+    match overflow {
+        DepthSortOverflow::DropNew => false,
+        DepthSortOverflow::DropFarthest => {
+            if let Some(farthest) = buffer.iter_mut().max_by_key(|e| e.depth)
+                && farthest.depth > entry.depth
+            {
+                *farthest = entry;
+            }
+            false
+        }
+        DepthSortOverflow::Flush => {
+            flush(buffer, callback);
+            buffer.push(entry).is_ok()
+        }
+    }
+}
+
+pub(crate) fn flush<F, const N: usize>(buffer: &mut Vec<DepthSortedPrimitive, N>, callback: &mut F)
+where
+    F: FnMut(DrawPrimitive),
+{
+    buffer.sort_unstable_by_key(|e| Reverse(e.depth));
+
+    for entry in buffer.iter() {
+        callback(entry.primitive);
+    }
+
+    buffer.clear();
+}
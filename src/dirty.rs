@@ -0,0 +1,201 @@
+use embedded_graphics_core::{
+    geometry::{Point, Size},
+    primitives::Rectangle,
+};
+
+pub const DIRTY_BANDS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Bounds {
+    min: Point,
+    max: Point,
+}
+
+impl Bounds {
+    fn union(self, other: Bounds) -> Bounds {
+        Bounds {
+            min: self.min.component_min(other.min),
+            max: self.max.component_max(other.max),
+        }
+    }
+
+    fn to_rectangle(self) -> Rectangle {
+        Rectangle::with_corners(self.min, self.max)
+    }
+}
+
+/// Union of the areas touched in a frame, kept both overall and per horizontal band of rows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DirtyRegion {
+    bands: [Option<Bounds>; DIRTY_BANDS],
+    band_height: i32,
+}
+
+impl DirtyRegion {
+    pub fn new(height: usize) -> DirtyRegion {
+        DirtyRegion {
+            bands: [None; DIRTY_BANDS],
+            band_height: height.div_ceil(DIRTY_BANDS).max(1) as i32,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bands.iter().all(Option::is_none)
+    }
+
+    pub fn clear(&mut self) {
+        self.bands = [None; DIRTY_BANDS];
+    }
+
+    #[inline]
+    pub fn add_point(&mut self, point: Point) {
+        self.add_bounds(Bounds {
+            min: point,
+            max: point,
+        });
+    }
+
+    pub fn add_rectangle(&mut self, rectangle: &Rectangle) {
+        let Some(bottom_right) = rectangle.bottom_right() else {
+            return;
+        };
+        let (top, bottom) = (rectangle.top_left.y, bottom_right.y);
+
+        // split the rectangle at band boundaries
+        let mut y = top;
+        while y <= bottom {
+            let band_end = ((y / self.band_height) + 1) * self.band_height - 1;
+            let end = band_end.min(bottom);
+
+            self.add_bounds(Bounds {
+                min: Point::new(rectangle.top_left.x, y),
+                max: Point::new(bottom_right.x, end),
+            });
+            y = end + 1;
+        }
+    }
+
+    #[inline]
+    fn add_bounds(&mut self, bounds: Bounds) {
+        let band = (bounds.min.y / self.band_height).clamp(0, DIRTY_BANDS as i32 - 1) as usize;
+
+        self.bands[band] = Some(match self.bands[band] {
+            Some(current) => current.union(bounds),
+            None => bounds,
+        });
+    }
+
+    pub fn union(&self, other: &DirtyRegion) -> DirtyRegion {
+        let mut ret = *self;
+        for (band, other) in ret.bands.iter_mut().zip(other.bands) {
+            *band = match (*band, other) {
+                (Some(a), Some(b)) => Some(a.union(b)),
+                (a, b) => a.or(b),
+            };
+        }
+        ret
+    }
+
+    pub fn bounding_rectangle(&self) -> Option<Rectangle> {
+        self.bands
+            .iter()
+            .flatten()
+            .copied()
+            .reduce(Bounds::union)
+            .map(Bounds::to_rectangle)
+    }
+
+    /// The touched rectangle of every band that has been drawn to, from top to bottom.
+    pub fn bands(&self) -> impl Iterator<Item = Rectangle> + '_ {
+        self.bands
+            .iter()
+            .flatten()
+            .map(|bounds| bounds.to_rectangle())
+    }
+
+    pub fn full(size: Size) -> DirtyRegion {
+        let mut ret = DirtyRegion::new(size.height as usize);
+        ret.add_rectangle(&Rectangle::new(Point::zero(), size));
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rectangles_are_split_at_band_boundaries() {
+        // 80 rows, 10 per band
+        let mut region = DirtyRegion::new(80);
+        region.add_rectangle(&Rectangle::new(Point::new(3, 5), Size::new(4, 20)));
+
+        let bands: [Rectangle; 3] = core::array::from_fn(|i| region.bands().nth(i).unwrap());
+        assert_eq!(region.bands().count(), 3);
+        assert_eq!(
+            bands,
+            [
+                Rectangle::with_corners(Point::new(3, 5), Point::new(6, 9)),
+                Rectangle::with_corners(Point::new(3, 10), Point::new(6, 19)),
+                Rectangle::with_corners(Point::new(3, 20), Point::new(6, 24)),
+            ]
+        );
+        assert_eq!(
+            region.bounding_rectangle(),
+            Some(Rectangle::new(Point::new(3, 5), Size::new(4, 20)))
+        );
+    }
+
+    #[test]
+    fn bands_are_tracked_separately() {
+        let mut region = DirtyRegion::new(80);
+        region.add_point(Point::new(1, 1));
+        region.add_point(Point::new(50, 70));
+        region.add_point(Point::new(60, 75));
+
+        let mut bands = region.bands();
+        assert_eq!(
+            bands.next(),
+            Some(Rectangle::new(Point::new(1, 1), Size::new(1, 1)))
+        );
+        assert_eq!(
+            bands.next(),
+            Some(Rectangle::with_corners(
+                Point::new(50, 70),
+                Point::new(60, 75)
+            ))
+        );
+        assert_eq!(bands.next(), None);
+    }
+
+    #[test]
+    fn union_and_clear() {
+        let mut a = DirtyRegion::new(80);
+        let mut b = DirtyRegion::new(80);
+        a.add_point(Point::new(1, 1));
+        b.add_point(Point::new(5, 2));
+        b.add_point(Point::new(5, 40));
+
+        let union = a.union(&b);
+        assert_eq!(union.bands().count(), 2);
+        assert_eq!(
+            union.bounding_rectangle(),
+            Some(Rectangle::with_corners(Point::new(1, 1), Point::new(5, 40)))
+        );
+
+        a.clear();
+        assert!(a.is_empty());
+        assert_eq!(a.bounding_rectangle(), None);
+    }
+
+    #[test]
+    fn full_covers_every_band() {
+        let region = DirtyRegion::full(Size::new(20, 80));
+
+        assert_eq!(region.bands().count(), DIRTY_BANDS);
+        assert_eq!(
+            region.bounding_rectangle(),
+            Some(Rectangle::new(Point::zero(), Size::new(20, 80)))
+        );
+    }
+}
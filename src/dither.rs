@@ -0,0 +1,246 @@
+use embedded_graphics_core::pixelcolor::{
+    Bgr555, Bgr565, Bgr666, Bgr888, BinaryColor, Gray2, Gray4, Gray8, PixelColor, Rgb555, Rgb565,
+    Rgb666, Rgb888, RgbColor,
+};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Dither {
+    #[default]
+    None,
+    /// 4x4 Bayer matrix anchored to the screen, stable across frames.
+    Ordered,
+    /// Quantization error carried to the next pixel of the same scanline.
+    ErrorDiffusion,
+}
+
+/// Colors that shaded triangles are quantized to, so that dithering works at the depth of the
+/// draw target rather than at Rgb565.
+pub trait DitherColor: PixelColor {
+    /// Highest value of each channel, gray colors use the same one for all three.
+    const MAX: [u8; 3];
+
+    /// The channels of `color` in 8 bits, before quantization.
+    fn channels(color: Rgb888) -> [u8; 3] {
+        [color.r(), color.g(), color.b()]
+    }
+
+    /// Color from channels already scaled to [`DitherColor::MAX`].
+    fn from_channels(channels: [u8; 3]) -> Self;
+}
+
+macro_rules! impl_rgb_dither_color {
+    ($($type:ty),*) => {
+        $(impl DitherColor for $type {
+            const MAX: [u8; 3] = [<$type>::MAX_R, <$type>::MAX_G, <$type>::MAX_B];
+
+            fn from_channels([r, g, b]: [u8; 3]) -> Self {
+                <$type>::new(r, g, b)
+            }
+        })*
+    };
+}
+
+impl_rgb_dither_color!(
+    Rgb555, Bgr555, Rgb565, Bgr565, Rgb666, Bgr666, Rgb888, Bgr888
+);
+
+/// Luma as computed by the embedded-graphics conversions to gray, ITU-R BT.601.
+fn luma(color: Rgb888) -> u8 {
+    let (r, g, b) = (color.r() as u32, color.g() as u32, color.b() as u32);
+    ((r * 77 + g * 150 + b * 29 + 128) / 256) as u8
+}
+
+macro_rules! impl_gray_dither_color {
+    ($($type:ty => $max:literal),*) => {
+        $(impl DitherColor for $type {
+            const MAX: [u8; 3] = [$max; 3];
+
+            fn channels(color: Rgb888) -> [u8; 3] {
+                [luma(color); 3]
+            }
+
+            fn from_channels([luma, _, _]: [u8; 3]) -> Self {
+                <$type>::new(luma)
+            }
+        })*
+    };
+}
+
+impl_gray_dither_color!(Gray2 => 3, Gray4 => 15, Gray8 => 255);
+
+impl DitherColor for BinaryColor {
+    const MAX: [u8; 3] = [1; 3];
+
+    fn channels(color: Rgb888) -> [u8; 3] {
+        [luma(color); 3]
+    }
+
+    fn from_channels([on, _, _]: [u8; 3]) -> Self {
+        (on != 0).into()
+    }
+}
+
+const BAYER_4X4: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Nearest level of an 8 bit channel with `max` levels.
+fn nearest(value: i32, max: i32) -> u8 {
+    ((value.clamp(0, 255) * max + 127) / 255) as u8
+}
+
+/// Value of a level back in 8 bits.
+fn expand(level: u8, max: i32) -> i32 {
+    (level as i32 * 255 + max / 2) / max
+}
+
+/// Level of an 8 bit channel with `max` levels, rounded up when the fractional part exceeds
+/// `threshold` sixteenths.
+fn ordered(value: i32, max: i32, threshold: u8) -> u8 {
+    // value * max / 255 + (threshold + 0.5) / 16, in 32ths of 255
+    let level = (value * max * 32 + (2 * threshold as i32 + 1) * 255) / (255 * 32);
+    level.min(max) as u8
+}
+
+/// Calls `pixel` with every column from `x_start` to `x_end` included on row `y`, along with
+/// `color` quantized to `C` with the given dithering.
+pub fn dither_span<C: DitherColor, F: FnMut(i32, C)>(
+    dither: Dither,
+    y: i32,
+    x_start: i32,
+    x_end: i32,
+    color: Rgb888,
+    mut pixel: F,
+) {
+    let source = C::channels(color).map(i32::from);
+    let max = C::MAX.map(i32::from);
+
+    match dither {
+        Dither::None => {
+            let color = C::from_channels(core::array::from_fn(|i| nearest(source[i], max[i])));
+            for x in x_start..=x_end {
+                pixel(x, color);
+            }
+        }
+        Dither::Ordered => {
+            let row = BAYER_4X4[(y & 3) as usize];
+            for x in x_start..=x_end {
+                let threshold = row[(x & 3) as usize];
+                let channels = core::array::from_fn(|i| ordered(source[i], max[i], threshold));
+
+                pixel(x, C::from_channels(channels));
+            }
+        }
+        Dither::ErrorDiffusion => {
+            let mut error = [0i32; 3];
+            for x in x_start..=x_end {
+                let channels = core::array::from_fn(|i| {
+                    let value = source[i] + error[i];
+                    let level = nearest(value, max[i]);
+                    error[i] = value - expand(level, max[i]);
+                    level
+                });
+
+                pixel(x, C::from_channels(channels));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_graphics_core::pixelcolor::GrayColor;
+
+    use super::*;
+    use crate::draw::draw;
+    use crate::formats::BinaryFramebuffer;
+    use crate::{DrawPrimitive, SubpixelPoint, Viewport};
+
+    /// Average of the first channel over 4 rows of 16 pixels, in 8 bits.
+    fn tile_average<C: Level>(dither: Dither, color: Rgb888) -> f32 {
+        let mut sum = 0;
+        for y in 0..4 {
+            dither_span(dither, y, 0, 15, color, |_, color: C| {
+                sum += expand(color.level(), C::MAX[0] as i32);
+            });
+        }
+        sum as f32 / 64.0
+    }
+
+    /// First channel of a quantized color.
+    trait Level: DitherColor {
+        fn level(self) -> u8;
+    }
+
+    impl Level for BinaryColor {
+        fn level(self) -> u8 {
+            self.is_on() as u8
+        }
+    }
+
+    impl Level for Gray4 {
+        fn level(self) -> u8 {
+            self.luma()
+        }
+    }
+
+    impl Level for Rgb565 {
+        fn level(self) -> u8 {
+            self.r()
+        }
+    }
+
+    #[test]
+    fn dithering_averages_to_the_source_at_any_depth() {
+        let gray = Rgb888::new(100, 100, 100);
+
+        for dither in [Dither::Ordered, Dither::ErrorDiffusion] {
+            assert!((tile_average::<BinaryColor>(dither, gray) - 100.0).abs() < 16.0);
+            assert!((tile_average::<Gray4>(dither, gray) - 100.0).abs() < 2.0);
+            assert!((tile_average::<Rgb565>(dither, gray) - 100.0).abs() < 1.0);
+        }
+    }
+
+    #[test]
+    fn no_dithering_rounds_to_the_nearest_level() {
+        let mut colors = [BinaryColor::Off; 2];
+        for (i, value) in [100, 160].into_iter().enumerate() {
+            dither_span(
+                Dither::None,
+                0,
+                0,
+                0,
+                Rgb888::new(value, value, value),
+                |_, c| {
+                    colors[i] = c;
+                },
+            );
+        }
+
+        assert_eq!(colors, [BinaryColor::Off, BinaryColor::On]);
+    }
+
+    #[test]
+    fn ordered_dithering_on_a_binary_framebuffer() {
+        let mut buffer = [0u8; 32 * 4];
+        let mut framebuffer = BinaryFramebuffer::<32, 32>::from_slice(&mut buffer).unwrap();
+        let gray = Rgb888::new(100, 100, 100);
+
+        // two triangles covering the framebuffer, in sub-pixels
+        let [a, b, c, d] =
+            [(0, 0), (512, 0), (512, 512), (0, 512)].map(|(x, y)| SubpixelPoint::new(x, y));
+        for vertices in [[a, b, c], [a, c, d]] {
+            draw(
+                DrawPrimitive::ShadedTriangle(
+                    vertices,
+                    gray,
+                    Dither::Ordered,
+                    Viewport::new(0, 0, 32, 32),
+                ),
+                &mut framebuffer,
+            );
+        }
+
+        let on: u32 = framebuffer.as_slice().iter().map(|b| b.count_ones()).sum();
+        let fraction = on as f32 / (32.0 * 32.0);
+        assert!((fraction - 100.0 / 255.0).abs() < 1.0 / 16.0, "{fraction}");
+    }
+}
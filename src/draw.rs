@@ -1,137 +1,548 @@
+use core::cmp::Ordering;
 use core::fmt::Debug;
 use embedded_graphics_core::draw_target::DrawTarget;
-use embedded_graphics_core::prelude::Point;
-use heapless::Vec;
+use embedded_graphics_core::pixelcolor::Rgb565;
+use embedded_graphics_core::prelude::{Point, Size};
+use embedded_graphics_core::primitives::Rectangle;
+use nalgebra::Point2;
 
 use crate::DrawPrimitive;
+use crate::SUBPIXEL_BITS;
+use crate::SubpixelPoint;
+use crate::Viewport;
+use crate::blend::{Blend, BlendMode, BlendTarget};
+use crate::dither::{DitherColor, dither_span};
+use crate::mesh::PointShape;
 
+/// Blended primitives are drawn opaque, see [`draw_blended`].
 #[inline]
-pub fn draw<D: DrawTarget<Color = embedded_graphics_core::pixelcolor::Rgb565>>(
-    primitive: DrawPrimitive,
-    fb: &mut D,
-) where
+pub fn draw<D: DrawTarget>(primitive: DrawPrimitive, fb: &mut D)
+where
     <D as DrawTarget>::Error: Debug,
+    D::Color: From<Rgb565> + DitherColor,
 {
     match primitive {
-        DrawPrimitive::Line([p1, p2], color) => {
+        DrawPrimitive::Line([p1, p2], color, viewport)
+        | DrawPrimitive::BlendedLine([p1, p2], color, _, viewport) => {
             fb.draw_iter(
                 line_drawing::Bresenham::new((p1.x, p1.y), (p2.x, p2.y))
-                    .map(|(x, y)| embedded_graphics_core::Pixel(Point::new(x, y), color)),
+                    .filter(|&(x, y)| viewport.contains(x, y))
+                    .map(|(x, y)| embedded_graphics_core::Pixel(Point::new(x, y), color.into())),
             )
             .unwrap();
         }
         DrawPrimitive::ColoredPoint(p, c) => {
             let p = embedded_graphics_core::geometry::Point::new(p.x, p.y);
 
-            fb.draw_iter([embedded_graphics_core::Pixel(p, c)]).unwrap();
+            fb.draw_iter([embedded_graphics_core::Pixel(p, c.into())])
+                .unwrap();
         }
-        DrawPrimitive::ColoredTriangle(mut vertices, color) => {
-            // sort vertices by y using bubble sort (since there are exactly 3 elements)
-            if vertices[0].y > vertices[1].y {
-                vertices.swap(0, 1);
-            }
-            if vertices[0].y > vertices[2].y {
-                vertices.swap(0, 2);
-            }
-            if vertices[1].y > vertices[2].y {
-                vertices.swap(1, 2);
-            }
+        DrawPrimitive::AntialiasedLine([p1, p2], color, background, viewport) => {
+            wu_line(p1, p2, &mut |x, y, coverage| {
+                if !viewport.contains(x, y) {
+                    return;
+                }
 
-            let mut buf: Vec<_, 3> = Vec::new();
-            for p in vertices.iter() {
-                buf.push(embedded_graphics_core::geometry::Point::new(p.x, p.y))
-                    .unwrap();
-            }
-            let [p1, p2, p3] = buf.into_array().unwrap();
-
-            if p2.y == p3.y {
-                fill_bottom_flat_triangle(p1, p2, p3, color, fb);
-            } else if p1.y == p2.y {
-                fill_top_flat_triangle(p1, p2, p3, color, fb);
-            } else {
-                let p4 = Point::new(
-                    (p1.x as f32
-                        + ((p2.y - p1.y) as f32 / (p3.y - p1.y) as f32) * (p3.x - p1.x) as f32)
-                        as i32,
-                    p2.y,
-                );
+                let color = match background {
+                    Some(background) => {
+                        Blend::new(coverage, BlendMode::Normal).apply(color, background)
+                    }
+                    // no way to read back, keep the pixels that are mostly covered
+                    None if coverage >= 128 => color,
+                    None => return,
+                };
+
+                fb.draw_iter([embedded_graphics_core::Pixel(
+                    Point::new(x, y),
+                    color.into(),
+                )])
+                .unwrap();
+            });
+        }
+        DrawPrimitive::ShadedTriangle(vertices, color, dither, viewport) => {
+            fill_triangle(vertices, &viewport, &mut |y, x_start, x_end| {
+                dither_span(dither, y, x_start, x_end, color, |x, color| {
+                    fb.draw_iter([embedded_graphics_core::Pixel(Point::new(x, y), color)])
+                        .unwrap();
+                });
+            });
+        }
+        DrawPrimitive::ColoredTriangle(vertices, color, viewport)
+        | DrawPrimitive::BlendedTriangle(vertices, color, _, viewport) => {
+            let color = color.into();
+            fill_triangle(vertices, &viewport, &mut |y, x_start, x_end| {
+                fill_span(fb, y, x_start, x_end, color);
+            });
+        }
+        DrawPrimitive::ThickLine([p1, p2], color, width, viewport) => {
+            let color = color.into();
+            fill_capsule(
+                (p1.x as f32, p1.y as f32),
+                (p2.x as f32, p2.y as f32),
+                width as f32 / 2.0,
+                &mut |y, x_start, x_end| fill_clipped_span(fb, &viewport, y, x_start, x_end, color),
+            );
+        }
+        DrawPrimitive::PointSprite(p, color, size, shape, viewport) => {
+            let color = color.into();
+            let size = size as i32;
+            let top_left = Point::new(p.x - (size - 1) / 2, p.y - (size - 1) / 2);
 
-                fill_bottom_flat_triangle(p1, p2, p4, color, fb);
-                fill_top_flat_triangle(p2, p4, p3, color, fb);
+            match shape {
+                PointShape::Square => {
+                    let sprite = Rectangle::new(top_left, Size::new(size as u32, size as u32));
+                    fb.fill_solid(&sprite.intersection(&viewport.rectangle()), color)
+                        .unwrap();
+                }
+                PointShape::Round => {
+                    let center = (
+                        top_left.x as f32 + (size - 1) as f32 / 2.0,
+                        top_left.y as f32 + (size - 1) as f32 / 2.0,
+                    );
+                    fill_capsule(
+                        center,
+                        center,
+                        size as f32 / 2.0,
+                        &mut |y, x_start, x_end| {
+                            fill_clipped_span(fb, &viewport, y, x_start, x_end, color)
+                        },
+                    );
+                }
             }
         }
     }
 }
 
-fn fill_bottom_flat_triangle<D: DrawTarget<Color = embedded_graphics_core::pixelcolor::Rgb565>>(
-    p1: Point,
-    p2: Point,
-    p3: Point,
-    color: embedded_graphics_core::pixelcolor::Rgb565,
+fn fill_span<D: DrawTarget>(fb: &mut D, y: i32, x_start: i32, x_end: i32, color: D::Color)
+where
+    <D as DrawTarget>::Error: Debug,
+{
+    fb.fill_solid(
+        &Rectangle::new(
+            Point::new(x_start, y),
+            Size::new((x_end - x_start + 1) as u32, 1),
+        ),
+        color,
+    )
+    .unwrap();
+}
+
+fn fill_clipped_span<D: DrawTarget>(
     fb: &mut D,
+    viewport: &Viewport,
+    y: i32,
+    x_start: i32,
+    x_end: i32,
+    color: D::Color,
 ) where
     <D as DrawTarget>::Error: Debug,
 {
-    let invslope1 = (p2.x - p1.x) as f32 / (p2.y - p1.y) as f32;
-    let invslope2 = (p3.x - p1.x) as f32 / (p3.y - p1.y) as f32;
+    if let Some((x_start, x_end)) = viewport.clip_span(y, x_start, x_end) {
+        fill_span(fb, y, x_start, x_end, color);
+    }
+}
 
-    let mut curx1 = p1.x as f32;
-    let mut curx2 = p1.x as f32;
+/// Like [`draw`], but blended primitives are blended with what the target already contains
+/// instead of being drawn opaque.
+#[inline]
+pub fn draw_blended<D: BlendTarget>(primitive: DrawPrimitive, fb: &mut D)
+where
+    <D as DrawTarget>::Error: Debug,
+{
+    match primitive {
+        DrawPrimitive::BlendedLine([p1, p2], color, blend, viewport) => {
+            for (x, y) in line_drawing::Bresenham::new((p1.x, p1.y), (p2.x, p2.y)) {
+                if viewport.contains(x, y) {
+                    fb.blend_pixel(Point::new(x, y), color, blend);
+                }
+            }
+        }
+        DrawPrimitive::BlendedTriangle(vertices, color, blend, viewport) => {
+            fill_triangle(vertices, &viewport, &mut |y, x_start, x_end| {
+                fb.blend_span(y, x_start, x_end, color, blend);
+            });
+        }
+        DrawPrimitive::AntialiasedLine([p1, p2], color, None, viewport) => {
+            wu_line(p1, p2, &mut |x, y, coverage| {
+                if !viewport.contains(x, y) {
+                    return;
+                }
 
-    for scanline_y in p1.y..=p2.y {
-        draw_horizontal_line(
-            Point::new(curx1 as i32, scanline_y),
-            Point::new(curx2 as i32, scanline_y),
-            color,
-            fb,
-        );
+                fb.blend_pixel(
+                    Point::new(x, y),
+                    color,
+                    Blend::new(coverage, BlendMode::Normal),
+                );
+            });
+        }
+        primitive => draw(primitive, fb),
+    }
+}
+
+/// Xiaolin Wu's line algorithm, calls `plot` with each pixel and its coverage.
+fn wu_line<P: FnMut(i32, i32, u8)>(p1: Point2<i32>, p2: Point2<i32>, plot: &mut P) {
+    let steep = (p2.y - p1.y).abs() > (p2.x - p1.x).abs();
 
-        curx1 += invslope1;
-        curx2 += invslope2;
+    // iterate along the major axis, from left to right
+    let (mut a, mut b) = if steep {
+        ((p1.y, p1.x), (p2.y, p2.x))
+    } else {
+        ((p1.x, p1.y), (p2.x, p2.y))
+    };
+    if a.0 > b.0 {
+        core::mem::swap(&mut a, &mut b);
+    }
+
+    let gradient = if a.0 == b.0 {
+        0.0
+    } else {
+        (b.1 - a.1) as f32 / (b.0 - a.0) as f32
+    };
+
+    let mut plot = |major: i32, minor: i32, coverage: f32| {
+        let coverage = (coverage * 255.0 + 0.5) as u8;
+        if coverage == 0 {
+            return;
+        }
+
+        if steep {
+            plot(minor, major, coverage);
+        } else {
+            plot(major, minor, coverage);
+        }
+    };
+
+    let mut minor = a.1 as f32;
+    for major in a.0..=b.0 {
+        let whole = minor.floor();
+        let fraction = minor - whole;
+
+        plot(major, whole as i32, 1.0 - fraction);
+        plot(major, whole as i32 + 1, fraction);
+
+        minor += gradient;
     }
 }
 
-fn fill_top_flat_triangle<D: DrawTarget<Color = embedded_graphics_core::pixelcolor::Rgb565>>(
-    p1: Point,
-    p2: Point,
-    p3: Point,
-    color: embedded_graphics_core::pixelcolor::Rgb565,
-    fb: &mut D,
-) where
-    <D as DrawTarget>::Error: Debug,
-{
-    let invslope1 = (p3.x - p1.x) as f32 / (p3.y - p1.y) as f32;
-    let invslope2 = (p3.x - p2.x) as f32 / (p3.y - p2.y) as f32;
+/// Calls `span` with every scanline of the pixels whose center is at most `radius` away from the
+/// segment `a`-`b`, i.e. a line with round caps. A single point gives a disk.
+fn fill_capsule<S: FnMut(i32, i32, i32)>(a: (f32, f32), b: (f32, f32), radius: f32, span: &mut S) {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let length_squared = dx * dx + dy * dy;
+    let length = length_squared.sqrt();
+
+    let top = (a.1.min(b.1) - radius).ceil() as i32;
+    let bottom = (a.1.max(b.1) + radius).floor() as i32;
+
+    for y in top..=bottom {
+        let row = y as f32;
+        let mut covered: Option<(f32, f32)> = None;
+        let mut add = |interval: Option<(f32, f32)>| {
+            if let Some((start, end)) = interval {
+                covered = Some(match covered {
+                    Some((s, e)) => (s.min(start), e.max(end)),
+                    None => (start, end),
+                });
+            }
+        };
 
-    let mut curx1 = p3.x as f32;
-    let mut curx2 = p3.x as f32;
+        // the caps
+        for center in [a, b] {
+            let offset = row - center.1;
+            let half = radius * radius - offset * offset;
+            if half >= 0.0 {
+                let half = half.sqrt();
+                add(Some((center.0 - half, center.0 + half)));
+            }
+        }
 
-    for scanline_y in (p1.y..=p3.y).rev() {
-        draw_horizontal_line(
-            Point::new(curx1 as i32, scanline_y),
-            Point::new(curx2 as i32, scanline_y),
-            color,
-            fb,
-        );
+        // the body, between the caps and within `radius` of the segment
+        if length_squared > 0.0 {
+            let body = clip_interval(
+                Some((f32::NEG_INFINITY, f32::INFINITY)),
+                dx,
+                dy * (row - a.1) - dx * a.0,
+                0.0,
+                length_squared,
+            );
+            add(clip_interval(
+                body,
+                -dy,
+                dx * (row - a.1) + dy * a.0,
+                -radius * length,
+                radius * length,
+            ));
+        }
 
-        curx1 -= invslope1;
-        curx2 -= invslope2;
+        if let Some((start, end)) = covered {
+            // half open so that even widths are not one pixel too wide
+            let (x_start, x_end) = (start.ceil() as i32, end.ceil() as i32 - 1);
+            if x_start <= x_end {
+                span(y, x_start, x_end);
+            }
+        }
     }
 }
 
-fn draw_horizontal_line<D: DrawTarget<Color = embedded_graphics_core::pixelcolor::Rgb565>>(
-    p1: Point,
-    p2: Point,
-    color: embedded_graphics_core::pixelcolor::Rgb565,
-    fb: &mut D,
-) where
-    <D as DrawTarget>::Error: Debug,
-{
-    let start = p1.x.min(p2.x);
-    let end = p1.x.max(p2.x);
+/// Restricts `interval` to the values of `x` for which `low <= slope * x + offset <= high`.
+fn clip_interval(
+    interval: Option<(f32, f32)>,
+    slope: f32,
+    offset: f32,
+    low: f32,
+    high: f32,
+) -> Option<(f32, f32)> {
+    let (start, end) = interval?;
 
-    for x in start..=end {
-        fb.draw_iter([embedded_graphics_core::Pixel(Point::new(x, p1.y), color)])
-            .unwrap();
+    if slope == 0.0 {
+        return (low..=high).contains(&offset).then_some((start, end));
+    }
+
+    let (x1, x2) = ((low - offset) / slope, (high - offset) / slope);
+    let (start, end) = (start.max(x1.min(x2)), end.min(x1.max(x2)));
+
+    (start <= end).then_some((start, end))
+}
+
+/// Edge functions of a triangle.
+pub(crate) struct Edges {
+    vertices: [(i64, i64); 3],
+    /// 1 or -1, so that the edge functions are positive inside whatever the winding.
+    sign: i64,
+    /// Makes pixel centers lying exactly on an edge belong only to top and left edges.
+    bias: [i64; 3],
+    pub(crate) area: i64,
+}
+
+impl Edges {
+    /// Returns `None` for degenerate triangles.
+    pub(crate) fn new(vertices: [SubpixelPoint; 3]) -> Option<Edges> {
+        let vertices = vertices.map(|p| (p.x as i64, p.y as i64));
+        let [a, b, c] = vertices;
+
+        let area = (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0);
+        if area == 0 {
+            return None;
+        }
+        let sign = area.signum();
+
+        let bias = core::array::from_fn(|i| {
+            let (p, q) = (vertices[(i + 1) % 3], vertices[(i + 2) % 3]);
+            let (dx, dy) = (sign * (q.0 - p.0), sign * (q.1 - p.1));
+            let top = dy == 0 && dx > 0;
+            let left = dy < 0;
+
+            if top || left { 0 } else { -1 }
+        });
+
+        Some(Edges {
+            vertices,
+            sign,
+            bias,
+            area: area.abs(),
+        })
+    }
+
+    /// Edge function of the edge opposite to vertex `i`, as `slope * x + offset` on row `y`.
+    fn edge(&self, i: usize, y: i64) -> (i64, i64) {
+        let (p, q) = (self.vertices[(i + 1) % 3], self.vertices[(i + 2) % 3]);
+        let slope = -(q.1 - p.1);
+        let offset = (q.0 - p.0) * (y - p.1) + (q.1 - p.1) * p.0;
+
+        (self.sign * slope, self.sign * offset)
+    }
+
+    /// Values of the edge functions at the sub-pixel position `x`, `y`, each of them is the
+    /// barycentric weight of the opposite vertex multiplied by [`Edges::area`].
+    pub(crate) fn weights(&self, x: i64, y: i64) -> [i64; 3] {
+        core::array::from_fn(|i| {
+            let (slope, offset) = self.edge(i, y);
+            slope * x + offset
+        })
+    }
+
+    pub(crate) fn contains(&self, weights: &[i64; 3]) -> bool {
+        weights
+            .iter()
+            .zip(self.bias)
+            .all(|(weight, bias)| weight + bias >= 0)
+    }
+
+    /// Rows whose pixel centers may be covered.
+    fn rows(&self) -> core::ops::RangeInclusive<i32> {
+        let [a, b, c] = self.vertices;
+        let (min, max) = (a.1.min(b.1).min(c.1), a.1.max(b.1).max(c.1));
+
+        (min - HALF).div_euclid(ONE) as i32..=(max - HALF).div_euclid(ONE) as i32
+    }
+
+    /// First and last column covered on row `y`, in pixels.
+    fn span(&self, y: i32) -> Option<(i32, i32)> {
+        let center = y as i64 * ONE + HALF;
+        let (mut start, mut end) = (i64::MIN, i64::MAX);
+
+        for i in 0..3 {
+            let (slope, offset) = self.edge(i, center);
+            // slope * (x * ONE + HALF) + offset + bias >= 0
+            let (slope, offset) = (slope * ONE, slope * HALF + offset + self.bias[i]);
+
+            match slope.cmp(&0) {
+                Ordering::Greater => start = start.max(-(offset.div_euclid(slope))),
+                Ordering::Less => end = end.min(offset.div_euclid(-slope)),
+                Ordering::Equal if offset < 0 => return None,
+                Ordering::Equal => {}
+            }
+        }
+
+        (start <= end).then_some((start as i32, end as i32))
+    }
+}
+
+const ONE: i64 = 1 << SUBPIXEL_BITS;
+const HALF: i64 = ONE / 2;
+
+/// Calls `span` with the row and the first and last column of every scanline of the triangle
+/// within `viewport`. A pixel is covered when its center is inside the triangle, centers on an
+/// edge shared by two triangles are covered by exactly one of them.
+fn fill_triangle<S: FnMut(i32, i32, i32)>(
+    vertices: [SubpixelPoint; 3],
+    viewport: &Viewport,
+    span: &mut S,
+) {
+    let Some(edges) = Edges::new(vertices) else {
+        return;
+    };
+
+    let rows = edges.rows();
+    let top = (*rows.start()).max(viewport.y as i32);
+    let bottom = (*rows.end()).min(viewport.y as i32 + viewport.height as i32 - 1);
+
+    for y in top..=bottom {
+        if let Some((x_start, x_end)) = edges
+            .span(y)
+            .and_then(|(x_start, x_end)| viewport.clip_span(y, x_start, x_end))
+        {
+            span(y, x_start, x_end);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_graphics_core::pixelcolor::RgbColor;
+
+    use super::*;
+    use crate::framebuffer::DmaReadyFramebuffer;
+
+    const SIZE: usize = 64;
+
+    fn coverage(triangles: &[[SubpixelPoint; 3]]) -> [[u8; SIZE]; SIZE] {
+        let mut counts = [[0u8; SIZE]; SIZE];
+        for triangle in triangles {
+            let viewport = Viewport::new(0, 0, SIZE as u16, SIZE as u16);
+            fill_triangle(*triangle, &viewport, &mut |y, x_start, x_end| {
+                for x in x_start..=x_end {
+                    counts[y as usize][x as usize] += 1;
+                }
+            });
+        }
+        counts
+    }
+
+    fn subpixel(x: f32, y: f32) -> SubpixelPoint {
+        SubpixelPoint::new((x * ONE as f32) as i32, (y * ONE as f32) as i32)
+    }
+
+    #[test]
+    fn fan_is_watertight() {
+        const TRIANGLES: usize = 13;
+        // on pixel centers, so that the shared edges go through many of them
+        let center = subpixel(32.5, 32.5);
+        let rim: [SubpixelPoint; TRIANGLES] = core::array::from_fn(|i| {
+            let (sin, cos) = (i as f32 * core::f32::consts::TAU / TRIANGLES as f32).sin_cos();
+            subpixel(
+                (32.0 + 25.0 * cos).floor() + 0.5,
+                (32.0 + 25.0 * sin).floor() + 0.5,
+            )
+        });
+        let fan: [[SubpixelPoint; 3]; TRIANGLES] =
+            core::array::from_fn(|i| [center, rim[i], rim[(i + 1) % TRIANGLES]]);
+
+        let counts = coverage(&fan);
+
+        // the fan is convex, a pixel center inside all the rim edges must be covered once
+        let inside = |x: i64, y: i64| {
+            (0..TRIANGLES).all(|i| {
+                let (p, q) = (rim[i], rim[(i + 1) % TRIANGLES]);
+                let (p, q) = ((p.x as i64, p.y as i64), (q.x as i64, q.y as i64));
+                (q.0 - p.0) * (y - p.1) - (q.1 - p.1) * (x - p.0) > 0
+            })
+        };
+        for (y, row) in counts.iter().enumerate() {
+            for (x, count) in row.iter().enumerate() {
+                let (cx, cy) = (x as i64 * ONE + HALF, y as i64 * ONE + HALF);
+                assert!(*count <= 1, "pixel {x}, {y} drawn {count} times");
+                if inside(cx, cy) {
+                    assert_eq!(*count, 1, "gap at pixel {x}, {y}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn centers_on_edges_follow_the_top_left_rule() {
+        // pixel centers lie exactly on all four sides
+        let [a, b, c, d] =
+            [(0.5, 0.5), (4.5, 0.5), (4.5, 4.5), (0.5, 4.5)].map(|(x, y)| subpixel(x, y));
+
+        for square in [[[a, b, c], [a, c, d]], [[a, c, b], [a, d, c]]] {
+            let counts = coverage(&square);
+
+            for (y, row) in counts.iter().enumerate() {
+                for (x, count) in row.iter().enumerate() {
+                    let expected = (x < 4 && y < 4) as u8;
+                    assert_eq!(*count, expected, "pixel {x}, {y}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn degenerate_triangles_cover_nothing() {
+        let line = [subpixel(1.0, 1.0), subpixel(5.0, 5.0), subpixel(9.0, 9.0)];
+
+        assert!(Edges::new(line).is_none());
+        assert!(coverage(&[line]).iter().flatten().all(|count| *count == 0));
+    }
+
+    #[test]
+    fn wide_primitives_stay_in_their_viewport() {
+        // the left half of the framebuffer, primitives centered on its last column
+        let viewport = Viewport::new(0, 0, 16, 32);
+        let (p1, p2) = (Point2::new(15, 4), Point2::new(15, 26));
+        let primitives = [
+            DrawPrimitive::PointSprite(p1, Rgb565::RED, 8, PointShape::Square, viewport),
+            DrawPrimitive::PointSprite(p2, Rgb565::RED, 8, PointShape::Round, viewport),
+            DrawPrimitive::ThickLine([p1, p2], Rgb565::RED, 6, viewport),
+            DrawPrimitive::AntialiasedLine(
+                [p1, Point2::new(16, 26)],
+                Rgb565::RED,
+                Some(Rgb565::BLACK),
+                viewport,
+            ),
+        ];
+
+        for primitive in primitives {
+            let mut rows = [[0u16; 32]; 32];
+            let mut framebuffer = DmaReadyFramebuffer::from_array(&mut rows, false);
+            draw(primitive, &mut framebuffer);
+
+            let drawn = |columns: core::ops::Range<usize>| {
+                rows.iter()
+                    .any(|row| row[columns.clone()].iter().any(|p| *p != 0))
+            };
+            assert!(drawn(0..16), "{primitive:?}");
+            assert!(!drawn(16..32), "{primitive:?}");
+        }
     }
 }
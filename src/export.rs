@@ -0,0 +1,231 @@
+use core::fmt::Write;
+
+use embedded_graphics_core::{
+    geometry::{OriginDimensions, Point},
+    pixelcolor::{IntoStorage, Rgb565, Rgb888, RgbColor},
+};
+use heapless::String;
+
+use crate::framebuffer::DmaReadyFramebuffer;
+
+const BMP_HEADER_LEN: u32 = 14 + 40 + 12;
+
+fn pixels<const W: usize, const H: usize>(
+    fb: &DmaReadyFramebuffer<'_, W, H>,
+    y: u32,
+) -> impl Iterator<Item = Rgb565> {
+    (0..fb.size().width).map(move |x| {
+        fb.get_pixel(Point::new(x as i32, y as i32))
+            .unwrap_or(Rgb565::BLACK)
+    })
+}
+
+/// Writes the framebuffer as it is seen by the renderer, as a binary (P6) PPM image.
+/// `sink` is called with consecutive chunks of the file.
+pub fn write_ppm<const W: usize, const H: usize, S, E>(
+    fb: &DmaReadyFramebuffer<'_, W, H>,
+    mut sink: S,
+) -> Result<(), E>
+where
+    S: FnMut(&[u8]) -> Result<(), E>,
+{
+    let size = fb.size();
+
+    let mut header: String<32> = String::new();
+    let _ = write!(header, "P6\n{} {}\n255\n", size.width, size.height);
+    sink(header.as_bytes())?;
+
+    let mut chunk = [0u8; 48];
+    for y in 0..size.height {
+        let mut len = 0;
+        for color in pixels(fb, y) {
+            let color = Rgb888::from(color);
+            chunk[len..len + 3].copy_from_slice(&[color.r(), color.g(), color.b()]);
+            len += 3;
+
+            if len == chunk.len() {
+                sink(&chunk)?;
+                len = 0;
+            }
+        }
+        sink(&chunk[..len])?;
+    }
+
+    Ok(())
+}
+
+/// Writes the framebuffer as an ASCII (P3) PPM image, for when only a text channel is available.
+pub fn write_ppm_ascii<const W: usize, const H: usize, T: Write>(
+    fb: &DmaReadyFramebuffer<'_, W, H>,
+    out: &mut T,
+) -> core::fmt::Result {
+    let size = fb.size();
+
+    write!(out, "P3\n{} {}\n255\n", size.width, size.height)?;
+    for y in 0..size.height {
+        for color in pixels(fb, y) {
+            let color = Rgb888::from(color);
+            write!(out, "{} {} {} ", color.r(), color.g(), color.b())?;
+        }
+        out.write_char('\n')?;
+    }
+
+    Ok(())
+}
+
+/// Writes the framebuffer as a 16 bit Rgb565 BMP image. `sink` is called with consecutive chunks
+/// of the file.
+pub fn write_bmp<const W: usize, const H: usize, S, E>(
+    fb: &DmaReadyFramebuffer<'_, W, H>,
+    mut sink: S,
+) -> Result<(), E>
+where
+    S: FnMut(&[u8]) -> Result<(), E>,
+{
+    let size = fb.size();
+    let row_len = (size.width * 2).next_multiple_of(4);
+    let image_len = row_len * size.height;
+
+    let mut header = [0u8; BMP_HEADER_LEN as usize];
+    // BITMAPFILEHEADER
+    header[0..2].copy_from_slice(b"BM");
+    header[2..6].copy_from_slice(&(BMP_HEADER_LEN + image_len).to_le_bytes());
+    header[10..14].copy_from_slice(&BMP_HEADER_LEN.to_le_bytes());
+    // BITMAPINFOHEADER
+    header[14..18].copy_from_slice(&40u32.to_le_bytes());
+    header[18..22].copy_from_slice(&size.width.to_le_bytes());
+    header[22..26].copy_from_slice(&size.height.to_le_bytes());
+    header[26..28].copy_from_slice(&1u16.to_le_bytes());
+    header[28..30].copy_from_slice(&16u16.to_le_bytes());
+    header[30..34].copy_from_slice(&3u32.to_le_bytes()); // BI_BITFIELDS
+    header[34..38].copy_from_slice(&image_len.to_le_bytes());
+    // channel masks
+    header[54..58].copy_from_slice(&0xF800u32.to_le_bytes());
+    header[58..62].copy_from_slice(&0x07E0u32.to_le_bytes());
+    header[62..66].copy_from_slice(&0x001Fu32.to_le_bytes());
+    sink(&header)?;
+
+    // rows are stored bottom to top, each padded to 4 bytes
+    let mut chunk = [0u8; 64];
+    for y in (0..size.height).rev() {
+        let mut len = 0;
+        for color in pixels(fb, y) {
+            chunk[len..len + 2].copy_from_slice(&color.into_storage().to_le_bytes());
+            len += 2;
+
+            if len == chunk.len() {
+                sink(&chunk)?;
+                len = 0;
+            }
+        }
+        sink(&chunk[..len])?;
+        sink(&[0u8; 2][..(row_len - size.width * 2) as usize])?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::{string::String, vec::Vec};
+
+    use embedded_graphics_core::pixelcolor::RgbColor;
+
+    use super::*;
+
+    /// 3x2, odd so that BMP rows need padding:
+    /// red   black white
+    /// black blue  black
+    fn with_framebuffer(test: impl FnOnce(&DmaReadyFramebuffer<'_, 3, 2>)) {
+        let mut rows = [[0u16; 3]; 2];
+        let mut framebuffer = DmaReadyFramebuffer::from_array(&mut rows, false);
+        framebuffer.set_pixel(Point::new(0, 0), Rgb565::RED);
+        framebuffer.set_pixel(Point::new(2, 0), Rgb565::WHITE);
+        framebuffer.set_pixel(Point::new(1, 1), Rgb565::BLUE);
+        test(&framebuffer);
+    }
+
+    fn collect(write: impl FnOnce(&mut dyn FnMut(&[u8]) -> Result<(), ()>)) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write(&mut |chunk| {
+            bytes.extend_from_slice(chunk);
+            Ok(())
+        });
+        bytes
+    }
+
+    fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn bmp_header_and_padded_bottom_up_rows() {
+        with_framebuffer(|framebuffer| {
+            let bytes = collect(|sink| write_bmp(framebuffer, sink).unwrap());
+
+            // 3 pixels of 2 bytes padded to 8 bytes per row
+            assert_eq!(bytes.len(), 66 + 2 * 8);
+            assert_eq!(&bytes[0..2], b"BM");
+            assert_eq!(u32_at(&bytes, 2), bytes.len() as u32);
+            assert_eq!(u32_at(&bytes, 10), 66);
+            assert_eq!(u32_at(&bytes, 14), 40);
+            assert_eq!(u32_at(&bytes, 18), 3);
+            assert_eq!(u32_at(&bytes, 22), 2);
+            assert_eq!(u16_at(&bytes, 26), 1);
+            assert_eq!(u16_at(&bytes, 28), 16);
+            assert_eq!(u32_at(&bytes, 30), 3);
+            assert_eq!(u32_at(&bytes, 34), 16);
+            assert_eq!(
+                [u32_at(&bytes, 54), u32_at(&bytes, 58), u32_at(&bytes, 62)],
+                [0xF800, 0x07E0, 0x001F]
+            );
+
+            let rows: Vec<[u16; 4]> = bytes[66..]
+                .chunks_exact(8)
+                .map(|row| core::array::from_fn(|x| u16_at(row, x * 2)))
+                .collect();
+            assert_eq!(
+                rows,
+                [[0, 0x001F, 0, 0], [0xF800, 0, 0xFFFF, 0]],
+                "bottom row first, padded with zeros"
+            );
+        });
+    }
+
+    #[test]
+    fn binary_ppm_is_top_down_rgb() {
+        with_framebuffer(|framebuffer| {
+            let bytes = collect(|sink| write_ppm(framebuffer, sink).unwrap());
+
+            let header = b"P6\n3 2\n255\n";
+            assert_eq!(&bytes[..header.len()], header);
+            #[rustfmt::skip]
+            assert_eq!(
+                &bytes[header.len()..],
+                &[
+                    255, 0, 0,   0, 0, 0,   255, 255, 255,
+                    0, 0, 0,     0, 0, 255, 0, 0, 0,
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn ascii_ppm_has_one_line_per_row() {
+        with_framebuffer(|framebuffer| {
+            let mut text = String::new();
+            write_ppm_ascii(framebuffer, &mut text).unwrap();
+
+            assert_eq!(
+                text,
+                "P3\n3 2\n255\n255 0 0 0 0 0 255 255 255 \n0 0 0 0 0 255 0 0 0 \n"
+            );
+        });
+    }
+}
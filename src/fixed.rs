@@ -0,0 +1,254 @@
+use core::ops::{Add, Div, Mul, Neg, Sub};
+
+use nalgebra::Matrix4;
+
+/// Q16.16 fixed-point number, used by the `fixed-point` feature instead of `f32` on the
+/// rendering hot path for microcontrollers without FPU.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Fixed(pub i32);
+
+impl Fixed {
+    pub const FRAC_BITS: u32 = 16;
+    pub const ZERO: Fixed = Fixed(0);
+    pub const ONE: Fixed = Fixed(1 << Self::FRAC_BITS);
+
+    /// Truncates towards zero and saturates like an `as` cast, but decodes the IEEE 754 bits
+    /// with integer operations only, so it costs no soft-float calls on the hot path.
+    pub fn from_f32(value: f32) -> Fixed {
+        let bits = value.to_bits();
+        let exponent = ((bits >> 23) & 0xff) as i32;
+        let mantissa = bits & 0x7f_ffff;
+
+        if exponent == 0xff && mantissa != 0 {
+            return Fixed::ZERO; // NaN
+        }
+        // value = (1.mantissa << 23) * 2^(exponent - 150), scaled by 2^FRAC_BITS;
+        // subnormals are far below the resolution and truncate to zero
+        let shift = exponent - 150 + Self::FRAC_BITS as i32;
+        let magnitude = match shift {
+            _ if exponent == 0 || shift < -24 => 0,
+            ..0 => ((mantissa | 0x80_0000) >> -shift) as i64,
+            // anything shifted further is out of range anyway
+            _ => ((mantissa | 0x80_0000) as i64) << shift.min(16),
+        };
+
+        Fixed::saturate(if bits >> 31 != 0 {
+            -magnitude
+        } else {
+            magnitude
+        })
+    }
+
+    /// Rounds to nearest like an `as` cast, with integer operations only like
+    /// [`Fixed::from_f32`].
+    pub fn to_f32(self) -> f32 {
+        if self.0 == 0 {
+            return 0.0;
+        }
+        let sign = self.0 as u32 & 0x8000_0000;
+        let magnitude = self.0.unsigned_abs();
+        let top = 31 - magnitude.leading_zeros();
+
+        let (mantissa, top) = if top <= 23 {
+            (magnitude << (23 - top), top)
+        } else {
+            // round half to even on the bits that don't fit
+            let shift = top - 23;
+            let rest = magnitude & ((1 << shift) - 1);
+            let half = 1 << (shift - 1);
+            let mut mantissa = magnitude >> shift;
+            if rest > half || (rest == half && mantissa & 1 == 1) {
+                mantissa += 1;
+            }
+            if mantissa == 1 << 24 {
+                (mantissa >> 1, top + 1)
+            } else {
+                (mantissa, top)
+            }
+        };
+
+        let exponent = (top as i32 - Self::FRAC_BITS as i32 + 127) as u32;
+        f32::from_bits(sign | exponent << 23 | (mantissa & 0x7f_ffff))
+    }
+
+    pub const fn from_int(value: i32) -> Fixed {
+        Fixed(value << Self::FRAC_BITS)
+    }
+
+    /// Integer part, rounded towards negative infinity.
+    pub const fn to_int(self) -> i32 {
+        self.0 >> Self::FRAC_BITS
+    }
+
+    /// Multiplies by an integer and returns the integer part, without the range limit of
+    /// converting `value` to [`Fixed`] first.
+    pub const fn mul_int(self, value: i32) -> i32 {
+        ((self.0 as i64 * value as i64) >> Self::FRAC_BITS) as i32
+    }
+
+    pub fn clamp(self, min: Fixed, max: Fixed) -> Fixed {
+        Fixed(self.0.clamp(min.0, max.0))
+    }
+
+    pub(crate) fn saturate(value: i64) -> Fixed {
+        Fixed(value.clamp(i32::MIN as i64, i32::MAX as i64) as i32)
+    }
+}
+
+impl Add for Fixed {
+    type Output = Fixed;
+
+    fn add(self, rhs: Fixed) -> Fixed {
+        Fixed(self.0.saturating_add(rhs.0))
+    }
+}
+
+impl Sub for Fixed {
+    type Output = Fixed;
+
+    fn sub(self, rhs: Fixed) -> Fixed {
+        Fixed(self.0.saturating_sub(rhs.0))
+    }
+}
+
+impl Mul for Fixed {
+    type Output = Fixed;
+
+    fn mul(self, rhs: Fixed) -> Fixed {
+        Fixed::saturate((self.0 as i64 * rhs.0 as i64) >> Fixed::FRAC_BITS)
+    }
+}
+
+/// Saturates instead of overflowing, panics on division by zero like integers do.
+impl Div for Fixed {
+    type Output = Fixed;
+
+    fn div(self, rhs: Fixed) -> Fixed {
+        Fixed::saturate(((self.0 as i64) << Fixed::FRAC_BITS) / rhs.0 as i64)
+    }
+}
+
+impl Neg for Fixed {
+    type Output = Fixed;
+
+    fn neg(self) -> Fixed {
+        Fixed(self.0.saturating_neg())
+    }
+}
+
+pub fn dot(a: &[Fixed; 3], b: &[Fixed; 3]) -> Fixed {
+    Fixed::saturate((0..3).map(|i| a[i].0 as i64 * b[i].0 as i64).sum::<i64>() >> Fixed::FRAC_BITS)
+}
+
+pub fn length(v: &[Fixed; 3]) -> Fixed {
+    let length_squared: i64 = v.iter().map(|c| c.0 as i64 * c.0 as i64).sum();
+    // square root of a Q32.32 number is Q16.16
+    Fixed::saturate(length_squared.unsigned_abs().isqrt() as i64)
+}
+
+/// Returns `v` unchanged if it has zero length.
+pub fn normalize(v: &[Fixed; 3]) -> [Fixed; 3] {
+    let length = length(v);
+
+    if length == Fixed::ZERO {
+        return *v;
+    }
+    v.map(|c| c / length)
+}
+
+pub fn vector(v: &[f32; 3]) -> [Fixed; 3] {
+    v.map(Fixed::from_f32)
+}
+
+/// Row-major 4x4 matrix, converted once from a nalgebra matrix and then applied to many points.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixedMatrix4([[Fixed; 4]; 4]);
+
+impl FixedMatrix4 {
+    pub fn from_matrix(matrix: &Matrix4<f32>) -> FixedMatrix4 {
+        FixedMatrix4(core::array::from_fn(|row| {
+            core::array::from_fn(|column| Fixed::from_f32(matrix[(row, column)]))
+        }))
+    }
+
+    pub fn transform(&self, v: &[Fixed; 4]) -> [Fixed; 4] {
+        self.0.map(|row| {
+            // accumulated at full precision, rounded once
+            Fixed::saturate(
+                (0..4).map(|i| row[i].0 as i64 * v[i].0 as i64).sum::<i64>() >> Fixed::FRAC_BITS,
+            )
+        })
+    }
+
+    /// Applies the upper 3x3 part, for directions.
+    pub fn transform_vector(&self, v: &[Fixed; 3]) -> [Fixed; 3] {
+        let [x, y, z, _] = self.transform(&[v[0], v[1], v[2], Fixed::ZERO]);
+        [x, y, z]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_f32_matches_a_float_cast() {
+        let values = [
+            0.0,
+            -0.0,
+            1.0,
+            -1.0,
+            0.5,
+            1.0 / 3.0,
+            -2.718_281_7,
+            1.5e-5,
+            -1.5e-5,
+            1.0e-40,
+            12_345.678,
+            -32_767.99,
+            32_768.0,
+            -32_768.0,
+            1.0e9,
+            -1.0e9,
+            f32::MAX,
+            f32::MIN,
+            f32::INFINITY,
+            f32::NEG_INFINITY,
+            f32::NAN,
+        ];
+
+        for value in values {
+            let expected = (value * Fixed::ONE.0 as f32) as i32;
+            assert_eq!(Fixed::from_f32(value), Fixed(expected), "{value}");
+        }
+    }
+
+    #[test]
+    fn to_f32_matches_a_float_cast() {
+        let values = [
+            0,
+            1,
+            -1,
+            Fixed::ONE.0,
+            -Fixed::ONE.0,
+            12_345_678,
+            // ties between two floats, rounded to the even one
+            (1 << 24) + 1,
+            (1 << 24) + 3,
+            (1 << 25) + 2,
+            (1 << 25) + 6,
+            0x7fff_ffc0,
+            i32::MAX,
+            i32::MIN,
+            i32::MIN + 1,
+        ];
+
+        for value in values
+            .into_iter()
+            .chain((0..4096).map(|i| i * 524_287 - (1 << 30)))
+        {
+            let expected = value as f32 / Fixed::ONE.0 as f32;
+            assert_eq!(Fixed(value).to_f32(), expected, "{value}");
+        }
+    }
+}
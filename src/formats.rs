@@ -0,0 +1,443 @@
+use core::marker::PhantomData;
+
+use embedded_graphics_core::{
+    Pixel,
+    draw_target::DrawTarget,
+    geometry::{OriginDimensions, Point, Size},
+    pixelcolor::{BinaryColor, Gray4, Gray8, GrayColor, PixelColor, Rgb666, Rgb888, RgbColor},
+    primitives::Rectangle,
+};
+
+/// Memory layout of a [`Framebuffer`], `x_end` is exclusive and coordinates are always in bounds.
+pub trait PixelFormat {
+    type Color: PixelColor;
+
+    fn buffer_len(width: usize, height: usize) -> usize;
+
+    fn set_pixel(buffer: &mut [u8], width: usize, x: usize, y: usize, color: Self::Color);
+
+    fn fill_span(
+        buffer: &mut [u8],
+        width: usize,
+        x: usize,
+        x_end: usize,
+        y: usize,
+        color: Self::Color,
+    ) {
+        for x in x..x_end {
+            Self::set_pixel(buffer, width, x, y, color);
+        }
+    }
+
+    fn clear(buffer: &mut [u8], width: usize, height: usize, color: Self::Color) {
+        for y in 0..height {
+            Self::fill_span(buffer, width, 0, width, y, color);
+        }
+    }
+}
+
+/// 24 bit packed, in R, G, B byte order.
+pub struct Rgb888Format;
+
+/// 18 bit color sent as 3 bytes with the channel in the upper 6 bits, as used by ILI9488 style
+/// controllers over SPI.
+pub struct Rgb666Format;
+
+pub struct Gray8Format;
+
+/// Two pixels per byte, the leftmost one in the upper nibble. Rows start on a byte boundary.
+pub struct Gray4Format;
+
+/// One bit per pixel in 8 pixel tall pages, each byte being a column of a page with the top pixel
+/// in the least significant bit, as used by SSD1306 and similar controllers.
+pub struct BinaryPageFormat;
+
+fn fill_rgb(buffer: &mut [u8], bytes: [u8; 3]) {
+    if bytes[0] == bytes[1] && bytes[1] == bytes[2] {
+        buffer.fill(bytes[0]);
+    } else {
+        for pixel in buffer.chunks_exact_mut(3) {
+            pixel.copy_from_slice(&bytes);
+        }
+    }
+}
+
+impl PixelFormat for Rgb888Format {
+    type Color = Rgb888;
+
+    fn buffer_len(width: usize, height: usize) -> usize {
+        width * height * 3
+    }
+
+    fn set_pixel(buffer: &mut [u8], width: usize, x: usize, y: usize, color: Rgb888) {
+        let i = (y * width + x) * 3;
+        buffer[i..i + 3].copy_from_slice(&[color.r(), color.g(), color.b()]);
+    }
+
+    fn fill_span(buffer: &mut [u8], width: usize, x: usize, x_end: usize, y: usize, color: Rgb888) {
+        let row = y * width;
+        fill_rgb(
+            &mut buffer[(row + x) * 3..(row + x_end) * 3],
+            [color.r(), color.g(), color.b()],
+        );
+    }
+
+    fn clear(buffer: &mut [u8], _width: usize, _height: usize, color: Rgb888) {
+        fill_rgb(buffer, [color.r(), color.g(), color.b()]);
+    }
+}
+
+impl PixelFormat for Rgb666Format {
+    type Color = Rgb666;
+
+    fn buffer_len(width: usize, height: usize) -> usize {
+        width * height * 3
+    }
+
+    fn set_pixel(buffer: &mut [u8], width: usize, x: usize, y: usize, color: Rgb666) {
+        let i = (y * width + x) * 3;
+        buffer[i..i + 3].copy_from_slice(&[color.r() << 2, color.g() << 2, color.b() << 2]);
+    }
+
+    fn fill_span(buffer: &mut [u8], width: usize, x: usize, x_end: usize, y: usize, color: Rgb666) {
+        let row = y * width;
+        fill_rgb(
+            &mut buffer[(row + x) * 3..(row + x_end) * 3],
+            [color.r() << 2, color.g() << 2, color.b() << 2],
+        );
+    }
+
+    fn clear(buffer: &mut [u8], _width: usize, _height: usize, color: Rgb666) {
+        fill_rgb(buffer, [color.r() << 2, color.g() << 2, color.b() << 2]);
+    }
+}
+
+impl PixelFormat for Gray8Format {
+    type Color = Gray8;
+
+    fn buffer_len(width: usize, height: usize) -> usize {
+        width * height
+    }
+
+    fn set_pixel(buffer: &mut [u8], width: usize, x: usize, y: usize, color: Gray8) {
+        buffer[y * width + x] = color.luma();
+    }
+
+    fn fill_span(buffer: &mut [u8], width: usize, x: usize, x_end: usize, y: usize, color: Gray8) {
+        buffer[y * width + x..y * width + x_end].fill(color.luma());
+    }
+
+    fn clear(buffer: &mut [u8], _width: usize, _height: usize, color: Gray8) {
+        buffer.fill(color.luma());
+    }
+}
+
+impl PixelFormat for Gray4Format {
+    type Color = Gray4;
+
+    fn buffer_len(width: usize, height: usize) -> usize {
+        width.div_ceil(2) * height
+    }
+
+    fn set_pixel(buffer: &mut [u8], width: usize, x: usize, y: usize, color: Gray4) {
+        let byte = &mut buffer[y * width.div_ceil(2) + x / 2];
+        if x.is_multiple_of(2) {
+            *byte = (*byte & 0x0F) | (color.luma() << 4);
+        } else {
+            *byte = (*byte & 0xF0) | color.luma();
+        }
+    }
+
+    fn fill_span(
+        buffer: &mut [u8],
+        width: usize,
+        mut x: usize,
+        mut x_end: usize,
+        y: usize,
+        color: Gray4,
+    ) {
+        if !x.is_multiple_of(2) && x < x_end {
+            Self::set_pixel(buffer, width, x, y, color);
+            x += 1;
+        }
+        if !x_end.is_multiple_of(2) && x < x_end {
+            Self::set_pixel(buffer, width, x_end - 1, y, color);
+            x_end -= 1;
+        }
+
+        let row = y * width.div_ceil(2);
+        buffer[row + x / 2..row + x_end / 2].fill(color.luma() << 4 | color.luma());
+    }
+
+    fn clear(buffer: &mut [u8], _width: usize, _height: usize, color: Gray4) {
+        buffer.fill(color.luma() << 4 | color.luma());
+    }
+}
+
+impl PixelFormat for BinaryPageFormat {
+    type Color = BinaryColor;
+
+    fn buffer_len(width: usize, height: usize) -> usize {
+        width * height.div_ceil(8)
+    }
+
+    fn set_pixel(buffer: &mut [u8], width: usize, x: usize, y: usize, color: BinaryColor) {
+        Self::fill_span(buffer, width, x, x + 1, y, color);
+    }
+
+    fn fill_span(
+        buffer: &mut [u8],
+        width: usize,
+        x: usize,
+        x_end: usize,
+        y: usize,
+        color: BinaryColor,
+    ) {
+        let row = (y / 8) * width;
+        let mask = 1 << (y % 8);
+
+        for byte in &mut buffer[row + x..row + x_end] {
+            if color.is_on() {
+                *byte |= mask;
+            } else {
+                *byte &= !mask;
+            }
+        }
+    }
+
+    fn clear(buffer: &mut [u8], _width: usize, _height: usize, color: BinaryColor) {
+        buffer.fill(if color.is_on() { 0xFF } else { 0x00 });
+    }
+}
+
+/// Framebuffer stored in the layout defined by `F`, see [`PixelFormat`].
+pub struct Framebuffer<'a, F, const W: usize, const H: usize> {
+    buffer: &'a mut [u8],
+    format: PhantomData<F>,
+}
+
+pub type Rgb888Framebuffer<'a, const W: usize, const H: usize> =
+    Framebuffer<'a, Rgb888Format, W, H>;
+pub type Rgb666Framebuffer<'a, const W: usize, const H: usize> =
+    Framebuffer<'a, Rgb666Format, W, H>;
+pub type Gray8Framebuffer<'a, const W: usize, const H: usize> = Framebuffer<'a, Gray8Format, W, H>;
+pub type Gray4Framebuffer<'a, const W: usize, const H: usize> = Framebuffer<'a, Gray4Format, W, H>;
+pub type BinaryFramebuffer<'a, const W: usize, const H: usize> =
+    Framebuffer<'a, BinaryPageFormat, W, H>;
+
+impl<'a, F: PixelFormat, const W: usize, const H: usize> Framebuffer<'a, F, W, H> {
+    pub fn buffer_len() -> usize {
+        F::buffer_len(W, H)
+    }
+
+    /// Returns `None` unless `buffer` is exactly [`Framebuffer::buffer_len`] bytes long.
+    pub fn from_slice(buffer: &'a mut [u8]) -> Option<Framebuffer<'a, F, W, H>> {
+        if buffer.len() != Self::buffer_len() {
+            return None;
+        }
+
+        Some(Framebuffer {
+            buffer,
+            format: PhantomData,
+        })
+    }
+
+    pub fn set_pixel(&mut self, point: Point, color: F::Color) {
+        if point.x >= 0 && point.x < W as i32 && point.y >= 0 && point.y < H as i32 {
+            F::set_pixel(self.buffer, W, point.x as usize, point.y as usize, color);
+        }
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        self.buffer
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        self.buffer
+    }
+}
+
+impl<F: PixelFormat, const W: usize, const H: usize> DrawTarget for Framebuffer<'_, F, W, H> {
+    type Color = F::Color;
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            self.set_pixel(point, color);
+        }
+        Ok(())
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        let area = area.intersection(&Rectangle::new(Point::zero(), self.size()));
+        let columns = area.columns();
+
+        for y in area.rows() {
+            F::fill_span(
+                self.buffer,
+                W,
+                columns.start as usize,
+                columns.end as usize,
+                y as usize,
+                color,
+            );
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        F::clear(self.buffer, W, H, color);
+        Ok(())
+    }
+}
+
+impl<F, const W: usize, const H: usize> OriginDimensions for Framebuffer<'_, F, W, H> {
+    fn size(&self) -> Size {
+        Size::new(W as u32, H as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gray4_at(buffer: &[u8], width: usize, x: usize, y: usize) -> u8 {
+        let byte = buffer[y * width.div_ceil(2) + x / 2];
+        if x.is_multiple_of(2) {
+            byte >> 4
+        } else {
+            byte & 0x0F
+        }
+    }
+
+    fn binary_at(buffer: &[u8], width: usize, x: usize, y: usize) -> bool {
+        buffer[(y / 8) * width + x] & (1 << (y % 8)) != 0
+    }
+
+    #[test]
+    fn gray4_packs_two_pixels_per_byte_with_padded_rows() {
+        let mut buffer = [0u8; 4];
+        let mut framebuffer = Gray4Framebuffer::<3, 2>::from_slice(&mut buffer).unwrap();
+
+        framebuffer.set_pixel(Point::new(0, 0), Gray4::new(0x3));
+        framebuffer.set_pixel(Point::new(1, 0), Gray4::new(0xA));
+        framebuffer.set_pixel(Point::new(2, 0), Gray4::new(0x5));
+        framebuffer.set_pixel(Point::new(1, 1), Gray4::new(0xF));
+
+        // the odd width leaves the lower nibble of the last byte of each row unused
+        assert_eq!(framebuffer.as_slice(), &[0x3A, 0x50, 0x0F, 0x00]);
+
+        for (x, y, luma) in [
+            (0, 0, 0x3),
+            (1, 0, 0xA),
+            (2, 0, 0x5),
+            (0, 1, 0),
+            (1, 1, 0xF),
+        ] {
+            assert_eq!(
+                gray4_at(framebuffer.as_slice(), 3, x, y),
+                luma,
+                "({x}, {y})"
+            );
+        }
+    }
+
+    #[test]
+    fn gray4_set_pixel_keeps_the_neighbouring_nibble() {
+        let mut buffer = [0u8; 4];
+        let mut framebuffer = Gray4Framebuffer::<4, 2>::from_slice(&mut buffer).unwrap();
+        framebuffer.clear(Gray4::new(0x7)).unwrap();
+
+        framebuffer.set_pixel(Point::new(3, 1), Gray4::new(0x1));
+        framebuffer.set_pixel(Point::new(2, 0), Gray4::new(0xC));
+
+        assert_eq!(framebuffer.as_slice(), &[0x77, 0xC7, 0x77, 0x71]);
+    }
+
+    #[test]
+    fn gray4_fill_span_handles_odd_ends() {
+        let mut buffer = [0u8; 3];
+        let mut framebuffer = Gray4Framebuffer::<5, 1>::from_slice(&mut buffer).unwrap();
+
+        framebuffer
+            .fill_solid(
+                &Rectangle::new(Point::new(1, 0), Size::new(3, 1)),
+                Gray4::new(0x9),
+            )
+            .unwrap();
+
+        assert_eq!(framebuffer.as_slice(), &[0x09, 0x99, 0x00]);
+        for x in 0..5 {
+            let expected = if (1..4).contains(&x) { 0x9 } else { 0 };
+            assert_eq!(
+                gray4_at(framebuffer.as_slice(), 5, x, 0),
+                expected,
+                "x = {x}"
+            );
+        }
+    }
+
+    #[test]
+    fn binary_pages_are_columns_of_eight_rows() {
+        let mut buffer = [0u8; 6];
+        let mut framebuffer = BinaryFramebuffer::<3, 10>::from_slice(&mut buffer).unwrap();
+
+        framebuffer.set_pixel(Point::new(1, 0), BinaryColor::On);
+        framebuffer.set_pixel(Point::new(1, 7), BinaryColor::On);
+        framebuffer.set_pixel(Point::new(1, 8), BinaryColor::On);
+        framebuffer.set_pixel(Point::new(2, 9), BinaryColor::On);
+
+        // the second page only uses its two lowest bits
+        assert_eq!(
+            framebuffer.as_slice(),
+            &[0x00, 0x81, 0x00, 0x00, 0x01, 0x02]
+        );
+
+        for y in 0..10 {
+            for x in 0..3 {
+                let expected = matches!((x, y), (1, 0) | (1, 7) | (1, 8) | (2, 9));
+                assert_eq!(
+                    binary_at(framebuffer.as_slice(), 3, x, y),
+                    expected,
+                    "({x}, {y})"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn binary_set_pixel_off_clears_only_its_bit() {
+        let mut buffer = [0u8; 6];
+        let mut framebuffer = BinaryFramebuffer::<3, 16>::from_slice(&mut buffer).unwrap();
+        framebuffer.clear(BinaryColor::On).unwrap();
+
+        framebuffer.set_pixel(Point::new(1, 7), BinaryColor::Off);
+        framebuffer.set_pixel(Point::new(1, 8), BinaryColor::Off);
+        framebuffer
+            .fill_solid(
+                &Rectangle::new(Point::new(0, 15), Size::new(3, 1)),
+                BinaryColor::Off,
+            )
+            .unwrap();
+
+        assert_eq!(
+            framebuffer.as_slice(),
+            &[0xFF, 0x7F, 0xFF, 0x7F, 0x7E, 0x7F]
+        );
+    }
+
+    #[test]
+    fn out_of_bounds_pixels_are_ignored() {
+        let mut buffer = [0u8; 2];
+        let mut framebuffer = BinaryFramebuffer::<2, 8>::from_slice(&mut buffer).unwrap();
+
+        framebuffer.set_pixel(Point::new(2, 0), BinaryColor::On);
+        framebuffer.set_pixel(Point::new(0, 8), BinaryColor::On);
+        framebuffer.set_pixel(Point::new(-1, 3), BinaryColor::On);
+
+        assert_eq!(framebuffer.as_slice(), &[0x00, 0x00]);
+    }
+}
@@ -1,57 +1,198 @@
+use core::{
+    cell::Cell,
+    future::poll_fn,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Poll, Waker},
+};
+
+use critical_section::Mutex;
+
 use embedded_graphics_core::{
     draw_target::DrawTarget,
-    geometry::{OriginDimensions, Point},
-    pixelcolor::{IntoStorage, Rgb565},
+    geometry::{OriginDimensions, Point, Size},
+    pixelcolor::{IntoStorage, Rgb565, raw::RawU16},
+    primitives::Rectangle,
 };
 
-pub struct DmaReadyFramebuffer<const W: usize, const H: usize> {
-    pub framebuffer: *mut [[u16; W]; H], // tfw no generic_const_exprs
+use crate::blend::{Blend, BlendTarget};
+use crate::dirty::DirtyRegion;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+    #[default]
+    Deg0,
+    Deg90,
+    Deg180,
+    Deg270,
+}
+
+/// How the drawing coordinates map to the buffer: mirroring is applied first, then the image is
+/// rotated clockwise.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Orientation {
+    pub rotation: Rotation,
+    pub mirror_x: bool,
+    pub mirror_y: bool,
+}
+
+pub struct DmaReadyFramebuffer<'a, const W: usize, const H: usize> {
+    pub framebuffer: &'a mut [[u16; W]; H], // tfw no generic_const_exprs
     big_endian: bool,
+    orientation: Orientation,
+    dirty: DirtyRegion,
+    erased: DirtyRegion,
 }
 
-impl<const W: usize, const H: usize> DmaReadyFramebuffer<W, H> {
-    pub fn new(
+impl<'a, const W: usize, const H: usize> DmaReadyFramebuffer<'a, W, H> {
+    /// # Safety
+    ///
+    /// `raw_framebuffer` must point to `W * H` properly aligned `u16`s that are valid for
+    /// reads and writes, and not accessed through any other pointer, for the whole lifetime `'a`.
+    pub unsafe fn new(
         raw_framebuffer: *mut ::core::ffi::c_void,
         big_endian: bool,
-    ) -> DmaReadyFramebuffer<W, H> {
+    ) -> DmaReadyFramebuffer<'a, W, H> {
         if raw_framebuffer.is_null() {
             panic!("Failed to allocate framebuffer");
         }
 
+        Self::from_array(
+            unsafe { &mut *(raw_framebuffer as *mut [[u16; W]; H]) },
+            big_endian,
+        )
+    }
+
+    /// Borrows the buffer for `'a`, a `&'static mut` buffer gives a `DmaReadyFramebuffer<'static, W, H>`.
+    pub fn from_array(
+        framebuffer: &'a mut [[u16; W]; H],
+        big_endian: bool,
+    ) -> DmaReadyFramebuffer<'a, W, H> {
         DmaReadyFramebuffer {
-            framebuffer: raw_framebuffer as *mut [[u16; W]; H],
+            framebuffer,
             big_endian,
+            orientation: Orientation::default(),
+            dirty: DirtyRegion::new(H),
+            erased: DirtyRegion::new(H),
+        }
+    }
+
+    /// Returns `None` unless `framebuffer` is exactly `W * H` pixels long.
+    pub fn from_slice(
+        framebuffer: &'a mut [u16],
+        big_endian: bool,
+    ) -> Option<DmaReadyFramebuffer<'a, W, H>> {
+        if W == 0 || framebuffer.len() != W * H {
+            return None;
+        }
+
+        let (rows, _) = framebuffer.as_chunks_mut::<W>();
+
+        Some(Self::from_array(rows.try_into().ok()?, big_endian))
+    }
+
+    pub fn set_orientation(&mut self, orientation: Orientation) {
+        self.orientation = orientation;
+    }
+
+    pub fn get_orientation(&self) -> Orientation {
+        self.orientation
+    }
+
+    /// Maps a point in drawing coordinates to the buffer, returns `None` if it is out of bounds.
+    fn to_physical(&self, point: Point) -> Option<Point> {
+        let size = self.size();
+        let (width, height) = (size.width as i32, size.height as i32);
+
+        if point.x < 0 || point.x >= width || point.y < 0 || point.y >= height {
+            return None;
         }
+
+        let x = if self.orientation.mirror_x {
+            width - 1 - point.x
+        } else {
+            point.x
+        };
+        let y = if self.orientation.mirror_y {
+            height - 1 - point.y
+        } else {
+            point.y
+        };
+
+        Some(match self.orientation.rotation {
+            Rotation::Deg0 => Point::new(x, y),
+            Rotation::Deg90 => Point::new(W as i32 - 1 - y, x),
+            Rotation::Deg180 => Point::new(W as i32 - 1 - x, H as i32 - 1 - y),
+            Rotation::Deg270 => Point::new(y, H as i32 - 1 - x),
+        })
     }
 
     pub fn set_pixel(&mut self, point: Point, color: Rgb565) {
-        if point.x >= 0 && point.x < W as i32 && point.y >= 0 && point.y < H as i32 {
-            unsafe {
-                let framebuffer = &mut *self.framebuffer;
-
-                if self.big_endian {
-                    framebuffer[point.y as usize][point.x as usize] = color.into_storage().to_be();
-                } else {
-                    framebuffer[point.y as usize][point.x as usize] = color.into_storage();
-                }
+        if let Some(point) = self.to_physical(point) {
+            self.dirty.add_point(point);
+            self.framebuffer[point.y as usize][point.x as usize] = self.raw_color(color);
+        }
+    }
+
+    pub fn get_pixel(&self, point: Point) -> Option<Rgb565> {
+        let point = self.to_physical(point)?;
+        let raw = self.framebuffer[point.y as usize][point.x as usize];
+
+        Some(Rgb565::from(RawU16::new(if self.big_endian {
+            u16::from_be(raw)
+        } else {
+            raw
+        })))
+    }
+
+    fn raw_color(&self, color: Rgb565) -> u16 {
+        if self.big_endian {
+            color.into_storage().to_be()
+        } else {
+            color.into_storage()
+        }
+    }
+
+    /// Area that changed since the last [`DmaReadyFramebuffer::clear_dirty`]: what has been drawn
+    /// plus what has been erased, this is what needs to be sent to the display.
+    pub fn dirty_region(&self) -> DirtyRegion {
+        self.dirty.union(&self.erased)
+    }
+
+    pub fn dirty_rectangle(&self) -> Option<Rectangle> {
+        self.dirty_region().bounding_rectangle()
+    }
+
+    /// Fills only the area drawn since the previous call with `color`, as a cheaper alternative
+    /// to `clear` when the previous frame has already been sent to the display.
+    pub fn clear_dirty(&mut self, color: Rgb565) {
+        let raw = self.raw_color(color);
+
+        for rectangle in self.dirty.bands() {
+            let columns = rectangle.columns();
+            for y in rectangle.rows() {
+                self.framebuffer[y as usize][columns.start as usize..columns.end as usize]
+                    .fill(raw);
             }
         }
+
+        self.erased = self.dirty;
+        self.dirty.clear();
     }
 
     pub fn as_slice(&self) -> &[u16] {
-        unsafe { core::slice::from_raw_parts(self.framebuffer as *const u16, W * H) }
+        self.framebuffer.as_flattened()
     }
 
     pub fn as_mut_slice(&mut self) -> &mut [u16] {
-        unsafe { core::slice::from_raw_parts_mut(self.framebuffer as *mut u16, W * H) }
+        self.framebuffer.as_flattened_mut()
     }
 
     pub fn as_mut_ptr(&mut self) -> *mut [u16] {
-        self.as_slice() as *const [u16] as *mut [u16]
+        self.as_mut_slice() as *mut [u16]
     }
 }
 
-impl<const W: usize, const H: usize> DrawTarget for DmaReadyFramebuffer<W, H> {
+impl<const W: usize, const H: usize> DrawTarget for DmaReadyFramebuffer<'_, W, H> {
     type Color = Rgb565;
     type Error = core::convert::Infallible;
 
@@ -67,19 +208,361 @@ impl<const W: usize, const H: usize> DrawTarget for DmaReadyFramebuffer<W, H> {
         Ok(())
     }
 
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        let area = area.intersection(&Rectangle::new(Point::zero(), self.size()));
+        let Some(bottom_right) = area.bottom_right() else {
+            return Ok(());
+        };
+
+        // rotations and mirroring map rectangles to rectangles
+        let (Some(a), Some(b)) = (
+            self.to_physical(area.top_left),
+            self.to_physical(bottom_right),
+        ) else {
+            return Ok(());
+        };
+        let area = Rectangle::with_corners(a, b);
+
+        let raw = self.raw_color(color);
+        let columns = area.columns();
+        for y in area.rows() {
+            self.framebuffer[y as usize][columns.start as usize..columns.end as usize].fill(raw);
+        }
+        self.dirty.add_rectangle(&area);
+
+        Ok(())
+    }
+
     fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
-        if self.big_endian {
-            self.as_mut_slice().fill(color.into_storage().to_be());
+        let raw = self.raw_color(color);
+        self.as_mut_slice().fill(raw);
+        self.dirty = DirtyRegion::full(Size::new(W as u32, H as u32));
+
+        Ok(())
+    }
+}
+
+impl<const W: usize, const H: usize> BlendTarget for DmaReadyFramebuffer<'_, W, H> {
+    fn blend_pixel(&mut self, point: Point, color: Rgb565, blend: Blend) {
+        if let Some(dst) = self.get_pixel(point) {
+            self.set_pixel(point, blend.apply(color, dst));
+        }
+    }
+}
+
+impl<const W: usize, const H: usize> OriginDimensions for DmaReadyFramebuffer<'_, W, H> {
+    fn size(&self) -> Size {
+        match self.orientation.rotation {
+            Rotation::Deg0 | Rotation::Deg180 => Size::new(W as u32, H as u32),
+            Rotation::Deg90 | Rotation::Deg270 => Size::new(H as u32, W as u32),
+        }
+    }
+}
+
+/// Whether the front buffer of a [`DoubleBuffer`] is still being transferred. Kept apart from the
+/// buffers, usually in a `static`, so that the DMA completion interrupt can release it while the
+/// renderer holds the `DoubleBuffer` mutably.
+pub struct FrontRelease {
+    busy: AtomicBool,
+    waker: Mutex<Cell<Option<Waker>>>,
+}
+
+impl Default for FrontRelease {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrontRelease {
+    pub const fn new() -> FrontRelease {
+        FrontRelease {
+            busy: AtomicBool::new(false),
+            waker: Mutex::new(Cell::new(None)),
+        }
+    }
+
+    fn acquire(&self) {
+        self.busy.store(true, Ordering::Release);
+    }
+
+    /// Marks the transfer as done and wakes the task waiting in [`FrontRelease::wait`], safe to
+    /// call from an interrupt.
+    pub fn release(&self) {
+        self.busy.store(false, Ordering::Release);
+
+        if let Some(waker) = critical_section::with(|cs| self.waker.borrow(cs).take()) {
+            waker.wake();
+        }
+    }
+
+    pub fn is_released(&self) -> bool {
+        !self.busy.load(Ordering::Acquire)
+    }
+
+    /// Sleeps until [`FrontRelease::release`] is called, only one task can wait at a time.
+    pub async fn wait(&self) {
+        poll_fn(|cx| {
+            if self.is_released() {
+                return Poll::Ready(());
+            }
+
+            critical_section::with(|cs| self.waker.borrow(cs).set(Some(cx.waker().clone())));
+
+            // released between the first check and the registration
+            if self.is_released() {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+}
+
+/// Two framebuffers, the renderer draws into the back one while the front one is transferred
+/// to the display.
+pub struct DoubleBuffer<'a, const W: usize, const H: usize> {
+    buffers: [DmaReadyFramebuffer<'a, W, H>; 2],
+    back: usize,
+    release: &'a FrontRelease,
+}
+
+impl<'a, const W: usize, const H: usize> DoubleBuffer<'a, W, H> {
+    pub fn new(
+        front: DmaReadyFramebuffer<'a, W, H>,
+        back: DmaReadyFramebuffer<'a, W, H>,
+        release: &'a FrontRelease,
+    ) -> DoubleBuffer<'a, W, H> {
+        DoubleBuffer {
+            buffers: [front, back],
+            back: 1,
+            release,
+        }
+    }
+
+    pub fn back_mut(&mut self) -> &mut DmaReadyFramebuffer<'a, W, H> {
+        &mut self.buffers[self.back]
+    }
+
+    pub fn front(&self) -> &[u16] {
+        self.buffers[1 - self.back].as_slice()
+    }
+
+    /// Marks the front buffer as in use until [`FrontRelease::release`] is called, typically
+    /// from the DMA completion interrupt through [`DoubleBuffer::release_handle`]. Returns the
+    /// front buffer for the transfer along with the back buffer, which can be drawn into while
+    /// the transfer runs.
+    pub fn begin_transfer(&mut self) -> (&[u16], &mut DmaReadyFramebuffer<'a, W, H>) {
+        self.release.acquire();
+
+        let [first, second] = &mut self.buffers;
+        let (front, back) = if self.back == 1 {
+            (first, second)
         } else {
-            self.as_mut_slice().fill(color.into_storage());
+            (second, first)
+        };
+        (front.as_slice(), back)
+    }
+
+    pub fn release_handle(&self) -> &'a FrontRelease {
+        self.release
+    }
+
+    pub fn release_front(&self) {
+        self.release.release();
+    }
+
+    pub fn is_front_released(&self) -> bool {
+        self.release.is_released()
+    }
+
+    /// Sleeps until the front buffer has been released.
+    pub async fn wait_front_released(&self) {
+        self.release.wait().await
+    }
+
+    /// Exchanges front and back buffers, returns false without swapping if the front buffer is
+    /// still being transferred.
+    pub fn swap(&mut self) -> bool {
+        if !self.is_front_released() {
+            return false;
         }
 
-        Ok(())
+        self.back = 1 - self.back;
+        true
     }
 }
 
-impl<const W: usize, const H: usize> OriginDimensions for DmaReadyFramebuffer<W, H> {
-    fn size(&self) -> embedded_graphics_core::geometry::Size {
-        embedded_graphics_core::geometry::Size::new(W as u32, H as u32)
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use core::{pin::pin, task::Context};
+    use std::{
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+        task::Wake,
+    };
+
+    use embedded_graphics_core::pixelcolor::RgbColor;
+
+    use super::*;
+
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn from_slice_checks_length() {
+        let mut buffer = [0u16; 4 * 3];
+
+        assert!(DmaReadyFramebuffer::<4, 3>::from_slice(&mut buffer[..11], false).is_none());
+        assert!(DmaReadyFramebuffer::<4, 3>::from_slice(&mut buffer, false).is_some());
+        assert!(DmaReadyFramebuffer::<3, 3>::from_slice(&mut buffer, false).is_none());
+        assert!(DmaReadyFramebuffer::<0, 3>::from_slice(&mut [], false).is_none());
+
+        let mut rows = [[0u16; 4]; 3];
+        let mut framebuffer = DmaReadyFramebuffer::from_array(&mut rows, false);
+        framebuffer.set_pixel(Point::new(1, 2), Rgb565::RED);
+        assert_eq!(
+            framebuffer.as_slice()[2 * 4 + 1],
+            Rgb565::RED.into_storage()
+        );
+    }
+
+    #[test]
+    fn orientation_maps_to_physical_pixels() {
+        let mut rows = [[0u16; 4]; 3];
+        let mut framebuffer = DmaReadyFramebuffer::from_array(&mut rows, false);
+        let point = Point::new(1, 0);
+
+        let cases = [
+            (Rotation::Deg0, false, false, Point::new(1, 0)),
+            (Rotation::Deg90, false, false, Point::new(3, 1)),
+            (Rotation::Deg180, false, false, Point::new(2, 2)),
+            (Rotation::Deg270, false, false, Point::new(0, 1)),
+            (Rotation::Deg0, true, false, Point::new(2, 0)),
+            (Rotation::Deg0, false, true, Point::new(1, 2)),
+            (Rotation::Deg90, true, false, Point::new(3, 1)),
+            (Rotation::Deg90, false, true, Point::new(0, 1)),
+        ];
+        for (rotation, mirror_x, mirror_y, physical) in cases {
+            framebuffer.set_orientation(Orientation {
+                rotation,
+                mirror_x,
+                mirror_y,
+            });
+
+            assert_eq!(
+                framebuffer.to_physical(point),
+                Some(physical),
+                "{rotation:?}"
+            );
+        }
+
+        framebuffer.set_orientation(Orientation {
+            rotation: Rotation::Deg90,
+            ..Default::default()
+        });
+        assert_eq!(framebuffer.size(), Size::new(3, 4));
+        assert_eq!(
+            framebuffer.to_physical(Point::new(2, 3)),
+            Some(Point::new(0, 2))
+        );
+        assert_eq!(framebuffer.to_physical(Point::new(3, 0)), None);
+        assert_eq!(framebuffer.to_physical(Point::new(0, -1)), None);
+    }
+
+    #[test]
+    fn rotated_fill_marks_the_physical_area_dirty() {
+        let mut rows = [[0u16; 4]; 3];
+        let mut framebuffer = DmaReadyFramebuffer::from_array(&mut rows, false);
+        framebuffer.set_orientation(Orientation {
+            rotation: Rotation::Deg90,
+            ..Default::default()
+        });
+
+        framebuffer
+            .fill_solid(
+                &Rectangle::new(Point::new(0, 0), Size::new(3, 1)),
+                Rgb565::BLUE,
+            )
+            .unwrap();
+
+        assert_eq!(
+            framebuffer.dirty_rectangle(),
+            Some(Rectangle::new(Point::new(3, 0), Size::new(1, 3)))
+        );
+        for y in 0..3 {
+            assert_eq!(framebuffer.framebuffer[y][3], Rgb565::BLUE.into_storage());
+        }
+    }
+
+    #[test]
+    fn big_endian_pixels_round_trip() {
+        let mut rows = [[0u16; 2]; 2];
+        let mut framebuffer = DmaReadyFramebuffer::from_array(&mut rows, true);
+        let color = Rgb565::new(1, 2, 3);
+
+        framebuffer.set_pixel(Point::new(1, 1), color);
+
+        assert_eq!(framebuffer.framebuffer[1][1], color.into_storage().to_be());
+        assert_eq!(framebuffer.get_pixel(Point::new(1, 1)), Some(color));
+    }
+
+    #[test]
+    fn release_wakes_the_waiting_task() {
+        let (mut front, mut back) = ([[0u16; 2]; 2], [[0u16; 2]; 2]);
+        let release = FrontRelease::new();
+        let mut buffers = DoubleBuffer::new(
+            DmaReadyFramebuffer::from_array(&mut front, false),
+            DmaReadyFramebuffer::from_array(&mut back, false),
+            &release,
+        );
+
+        buffers.begin_transfer();
+        assert!(!buffers.swap());
+
+        let wakes = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let waker = Waker::from(wakes.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut wait = pin!(buffers.release_handle().wait());
+
+        assert!(wait.as_mut().poll(&mut cx).is_pending());
+        assert!(wait.as_mut().poll(&mut cx).is_pending());
+        assert_eq!(wakes.0.load(Ordering::Relaxed), 0);
+
+        // from the interrupt, while the renderer holds the buffers
+        release.release();
+        assert_eq!(wakes.0.load(Ordering::Relaxed), 1);
+        assert!(wait.as_mut().poll(&mut cx).is_ready());
+        assert!(buffers.swap());
+    }
+
+    #[test]
+    fn back_buffer_is_drawn_during_the_transfer() {
+        let (mut front, mut back) = ([[0u16; 2]; 2], [[0u16; 2]; 2]);
+        let release = FrontRelease::new();
+        let mut buffers = DoubleBuffer::new(
+            DmaReadyFramebuffer::from_array(&mut front, false),
+            DmaReadyFramebuffer::from_array(&mut back, false),
+            &release,
+        );
+
+        let (transfer, back) = buffers.begin_transfer();
+        back.set_pixel(Point::new(1, 0), Rgb565::GREEN);
+        assert_eq!(transfer, &[0; 4]);
+        assert!(!release.is_released());
+
+        release.release();
+        assert!(buffers.swap());
+        assert_eq!(buffers.front()[1], Rgb565::GREEN.into_storage());
+        assert_eq!(buffers.back_mut().as_slice(), &[0; 4]);
     }
 }
@@ -1,32 +1,293 @@
 #![no_std]
-#![no_main]
+#![cfg_attr(not(test), no_main)]
+use band::BandTarget;
+use band::BinnedPrimitive;
+use blend::Blend;
+use blend::BlendTarget;
 use camera::Camera;
+use camera::Frustum;
+use core::fmt::Debug;
+use depth_sort::DepthSortKey;
+use depth_sort::DepthSortSettings;
+use depth_sort::DepthSortedPrimitive;
+use dither::Dither;
+use dither::DitherColor;
+use embedded_graphics_core::draw_target::DrawTarget;
+use embedded_graphics_core::geometry::Point;
+use embedded_graphics_core::geometry::Size;
 use embedded_graphics_core::pixelcolor::Rgb565;
-use embedded_graphics_core::pixelcolor::RgbColor;
+use embedded_graphics_core::pixelcolor::Rgb888;
+use embedded_graphics_core::primitives::Rectangle;
+#[cfg(feature = "fixed-point")]
+use fixed::Fixed;
+#[cfg(feature = "fixed-point")]
+use fixed::FixedMatrix4;
+use heapless::Vec;
+use lighting::FaceNormals;
+use lighting::Lighting;
 use mesh::K3dMesh;
+use mesh::PointShape;
 use mesh::RenderMode;
 use nalgebra::Matrix4;
 use nalgebra::Point2;
 use nalgebra::Point3;
 use nalgebra::Vector3;
+use nalgebra::Vector4;
+use picking::PickHit;
+use picking::Ray;
+use scene::Scene;
+use shader::FragmentShader;
+use shader::ProjectedVertex;
+use shader::VertexInput;
+use shader::VertexShader;
 
+pub mod band;
+pub mod blend;
 pub mod camera;
+pub mod depth_sort;
+pub mod dirty;
+pub mod dither;
 pub mod draw;
+pub mod export;
+#[cfg(feature = "fixed-point")]
+pub mod fixed;
+pub mod formats;
 pub mod framebuffer;
+mod lighting;
 pub mod mesh;
 pub mod perfcounter;
+pub mod picking;
+pub mod scene;
+pub mod shader;
 
-#[derive(Debug)]
+/// Fractional bits of [`SubpixelPoint`] coordinates.
+pub const SUBPIXEL_BITS: u32 = 4;
+
+/// Screen position in 28.4 fixed point, used for triangle vertices of [`DrawPrimitive`] while the
+/// other primitives are in whole pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubpixelPoint {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl SubpixelPoint {
+    pub fn new(x: i32, y: i32) -> SubpixelPoint {
+        SubpixelPoint { x, y }
+    }
+
+    /// The top left corner of a pixel.
+    pub fn from_pixel(point: Point2<i32>) -> SubpixelPoint {
+        SubpixelPoint::new(point.x << SUBPIXEL_BITS, point.y << SUBPIXEL_BITS)
+    }
+
+    /// The pixel containing the point.
+    pub fn to_pixel(self) -> Point2<i32> {
+        Point2::new(self.x >> SUBPIXEL_BITS, self.y >> SUBPIXEL_BITS)
+    }
+}
+
+/// Depth values of transformed points go from 0 on the near plane to this value on the far plane.
+pub const DEPTH_RESOLUTION: f32 = 16_777_215.0;
+
+/// Vertices further than this many sub-pixels from the screen origin are dropped along with their
+/// primitives, which keeps the edge functions of the rasterizer from overflowing. Closer ones are
+/// kept even outside of the viewport, the primitives are clipped when drawn.
+const GUARD_BAND: i32 = 1 << 26;
+
+/// Lines and triangles may extend past the edges of their viewport and are clipped to it when
+/// drawn, points are only emitted inside of it.
+#[derive(Debug, Clone, Copy)]
 pub enum DrawPrimitive {
     ColoredPoint(Point2<i32>, Rgb565),
-    Line([Point2<i32>; 2], Rgb565),
-    ColoredTriangle([Point2<i32>; 3], Rgb565),
+    Line([Point2<i32>; 2], Rgb565, Viewport),
+    ColoredTriangle([SubpixelPoint; 3], Rgb565, Viewport),
+    BlendedLine([Point2<i32>; 2], Rgb565, Blend, Viewport),
+    BlendedTriangle([SubpixelPoint; 3], Rgb565, Blend, Viewport),
+    /// Quantized to the color depth of the draw target by [`draw::draw`], see
+    /// [`dither::DitherColor`], with the given dithering.
+    ShadedTriangle([SubpixelPoint; 3], Rgb888, Dither, Viewport),
+    /// Anti-aliased line, blended against the given background color or, if `None`, against the
+    /// framebuffer content. The coverage is clipped to the viewport.
+    AntialiasedLine([Point2<i32>; 2], Rgb565, Option<Rgb565>, Viewport),
+    /// Line of the given width with round caps, clipped to the viewport.
+    ThickLine([Point2<i32>; 2], Rgb565, u8, Viewport),
+    /// Point of the given size centered on the position, clipped to the viewport.
+    PointSprite(Point2<i32>, Rgb565, u8, PointShape, Viewport),
+}
+
+impl DrawPrimitive {
+    /// Turns lines and triangles into their blended variant, other primitives are unchanged.
+    pub fn with_blend(self, blend: Blend) -> DrawPrimitive {
+        match self {
+            DrawPrimitive::Line(points, color, viewport) => {
+                DrawPrimitive::BlendedLine(points, color, blend, viewport)
+            }
+            DrawPrimitive::ColoredTriangle(points, color, viewport) => {
+                DrawPrimitive::BlendedTriangle(points, color, blend, viewport)
+            }
+            DrawPrimitive::ShadedTriangle(points, color, _, viewport) => {
+                DrawPrimitive::BlendedTriangle(points, color.into(), blend, viewport)
+            }
+            primitive => primitive,
+        }
+    }
+}
+
+/// Translucent meshes beyond this count are not depth sorted by [`K3dengine::render`].
+pub const MAX_TRANSLUCENT_MESHES: usize = 16;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RenderStats {
+    pub meshes_rendered: usize,
+    pub meshes_culled: usize,
+    pub faces_culled: usize,
+    pub backfaces_culled: usize,
+    pub primitives_dropped: usize,
+}
+
+/// Screen-space position of a vertex, in sub-pixels (see [`SUBPIXEL_BITS`]), transformed at most
+/// once per mesh by [`K3dengine::render_with_cache`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CachedVertex {
+    #[default]
+    Empty,
+    Visible(Point3<i32>),
+    Clipped,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Viewport {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl Viewport {
+    pub fn new(x: u16, y: u16, width: u16, height: u16) -> Viewport {
+        Viewport {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub fn aspect_ratio(&self) -> f32 {
+        self.width as f32 / self.height as f32
+    }
+
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x as i32
+            && x < self.x as i32 + self.width as i32
+            && y >= self.y as i32
+            && y < self.y as i32 + self.height as i32
+    }
+
+    /// Restricts the columns from `x_start` to `x_end` included on row `y` to the viewport.
+    pub(crate) fn clip_span(&self, y: i32, x_start: i32, x_end: i32) -> Option<(i32, i32)> {
+        if y < self.y as i32 || y >= self.y as i32 + self.height as i32 {
+            return None;
+        }
+
+        let x_start = x_start.max(self.x as i32);
+        let x_end = x_end.min(self.x as i32 + self.width as i32 - 1);
+        (x_start <= x_end).then_some((x_start, x_end))
+    }
+
+    pub fn rectangle(&self) -> Rectangle {
+        Rectangle::new(
+            Point::new(self.x as i32, self.y as i32),
+            Size::new(self.width as u32, self.height as u32),
+        )
+    }
+}
+
+/// Model-view-projection matrix in the form used by `transform_point`.
+#[cfg(not(feature = "fixed-point"))]
+type Transform = Matrix4<f32>;
+
+#[cfg(feature = "fixed-point")]
+struct Transform {
+    matrix: FixedMatrix4,
+    near: Fixed,
+    far: Fixed,
+}
+
+/// Point sizes scaled by the distance to the camera, see [`K3dMesh::set_point_attenuation`].
+#[cfg(not(feature = "fixed-point"))]
+struct PointAttenuation {
+    camera: Point3<f32>,
+    model_matrix: Matrix4<f32>,
+    /// Point size times the reference distance.
+    scale: f32,
+}
+
+#[cfg(not(feature = "fixed-point"))]
+impl PointAttenuation {
+    fn new(
+        camera: &Camera,
+        model_matrix: &Matrix4<f32>,
+        size: u8,
+        reference_distance: f32,
+    ) -> Self {
+        PointAttenuation {
+            camera: camera.position,
+            model_matrix: *model_matrix,
+            scale: size as f32 * reference_distance,
+        }
+    }
+
+    fn size(&self, vertex: &[f32; 3]) -> u8 {
+        let distance = nalgebra::distance(
+            &self.camera,
+            &self.model_matrix.transform_point(&Point3::from(*vertex)),
+        );
+        (self.scale / distance).clamp(1.0, 255.0) as u8
+    }
+}
+
+#[cfg(feature = "fixed-point")]
+struct PointAttenuation {
+    /// The model matrix followed by a translation putting the camera at the origin.
+    relative: FixedMatrix4,
+    scale: Fixed,
+}
+
+#[cfg(feature = "fixed-point")]
+impl PointAttenuation {
+    fn new(
+        camera: &Camera,
+        model_matrix: &Matrix4<f32>,
+        size: u8,
+        reference_distance: f32,
+    ) -> Self {
+        let relative = Matrix4::new_translation(&-camera.position.coords) * model_matrix;
+
+        PointAttenuation {
+            relative: FixedMatrix4::from_matrix(&relative),
+            scale: Fixed::from_f32(size as f32 * reference_distance),
+        }
+    }
+
+    fn size(&self, vertex: &[f32; 3]) -> u8 {
+        let [x, y, z] = fixed::vector(vertex);
+        let [x, y, z, _] = self.relative.transform(&[x, y, z, Fixed::ONE]);
+        let distance = fixed::length(&[x, y, z]);
+
+        if distance == Fixed::ZERO {
+            return u8::MAX;
+        }
+        (self.scale / distance).to_int().clamp(1, 255) as u8
+    }
 }
 
 pub struct K3dengine {
     pub camera: Camera,
     width: u16,
     height: u16,
+    viewport: Viewport,
 }
 
 impl K3dengine {
@@ -35,12 +296,54 @@ impl K3dengine {
             camera: Camera::new(width as f32 / height as f32),
             width,
             height,
+            viewport: Viewport::new(0, 0, width, height),
         }
     }
 
-    fn transform_point(&self, point: &[f32; 3], model_matrix: Matrix4<f32>) -> Option<Point3<i32>> {
+    /// Changes the screen size, e.g. after rotating the framebuffer. The viewport is reset to the
+    /// whole screen.
+    pub fn set_screen_size(&mut self, width: u16, height: u16) {
+        self.width = width;
+        self.height = height;
+        self.set_viewport(Viewport::new(0, 0, width, height));
+    }
+
+    /// Restricts rendering to a region of the screen, the camera aspect ratio follows the viewport.
+    /// The viewport is clamped to the screen size.
+    pub fn set_viewport(&mut self, viewport: Viewport) {
+        let x = viewport.x.min(self.width.saturating_sub(1));
+        let y = viewport.y.min(self.height.saturating_sub(1));
+
+        self.viewport = Viewport::new(
+            x,
+            y,
+            viewport.width.min(self.width - x).max(1),
+            viewport.height.min(self.height - y).max(1),
+        );
+        self.camera.set_aspect_ratio(self.viewport.aspect_ratio());
+    }
+
+    pub fn get_viewport(&self) -> Viewport {
+        self.viewport
+    }
+
+    fn mesh_transform(&self, model_matrix: &Matrix4<f32>) -> Transform {
+        let matrix = self.camera.vp_matrix * model_matrix;
+
+        #[cfg(feature = "fixed-point")]
+        let matrix = Transform {
+            matrix: FixedMatrix4::from_matrix(&matrix),
+            near: Fixed::from_f32(self.camera.near),
+            far: Fixed::from_f32(self.camera.far),
+        };
+
+        matrix
+    }
+
+    #[cfg(not(feature = "fixed-point"))]
+    fn transform_point(&self, point: &[f32; 3], transform: &Transform) -> Option<Point3<i32>> {
         let point = nalgebra::Vector4::new(point[0], point[1], point[2], 1.0);
-        let point = model_matrix * point;
+        let point = transform * point;
 
         if point.w < 0.0 {
             return None;
@@ -51,177 +354,712 @@ impl K3dengine {
 
         let point = Point3::from_homogeneous(point)?;
 
-        let x = ((1.0 + point.x) * 0.5 * self.width as f32) as i32;
-        let y = ((1.0 - point.y) * 0.5 * self.height as f32) as i32;
+        let scale = (1 << SUBPIXEL_BITS) as f32;
+        let x =
+            (self.viewport.x as f32 + (1.0 + point.x) * 0.5 * self.viewport.width as f32) * scale;
+        let y =
+            (self.viewport.y as f32 + (1.0 - point.y) * 0.5 * self.viewport.height as f32) * scale;
 
-        if x < 0 || x >= self.width as i32 || y < 0 || y >= self.height as i32 {
+        // also rejects NaN
+        if !(x.abs() <= GUARD_BAND as f32 && y.abs() <= GUARD_BAND as f32) {
             return None;
         }
 
+        Some(Point3::new(
+            x as i32,
+            y as i32,
+            ((point.z * 0.5 + 0.5) * DEPTH_RESOLUTION) as i32,
+        ))
+    }
+
+    #[cfg(feature = "fixed-point")]
+    fn transform_point(&self, point: &[f32; 3], transform: &Transform) -> Option<Point3<i32>> {
+        let [x, y, z] = fixed::vector(point);
+        let [x, y, z, w] = transform.matrix.transform(&[x, y, z, Fixed::ONE]);
+
+        if w <= Fixed::ZERO {
+            return None;
+        }
+        if z < transform.near || z > transform.far {
+            return None;
+        }
+
+        // in Q16.16 but wider, off-screen vertices can be out of the range of `Fixed`
+        let ndc = |c: Fixed| ((c.0 as i64) << Fixed::FRAC_BITS) / w.0 as i64;
+        let to_subpixel = |ndc: i64, offset: u16, size: u16| {
+            if ndc.abs() > (GUARD_BAND as i64) << Fixed::FRAC_BITS {
+                return None;
+            }
+            let subpixel = ((offset as i64) << SUBPIXEL_BITS)
+                + (((Fixed::ONE.0 as i64 + ndc) * ((size as i64) << (SUBPIXEL_BITS - 1)))
+                    >> Fixed::FRAC_BITS);
+            (subpixel.abs() <= GUARD_BAND as i64).then_some(subpixel as i32)
+        };
+
+        let x = to_subpixel(ndc(x), self.viewport.x, self.viewport.width)?;
+        let y = to_subpixel(-ndc(y), self.viewport.y, self.viewport.height)?;
+        let z = z / w;
+
         Some(Point3::new(
             x,
             y,
-            (point.z * (self.camera.far - self.camera.near) + self.camera.near) as i32,
+            (Fixed::ONE + z).mul_int((DEPTH_RESOLUTION / 2.0) as i32),
         ))
     }
 
+    pub fn screen_to_ray(&self, x: i32, y: i32) -> Ray {
+        let ndc_x =
+            (x as f32 - self.viewport.x as f32 + 0.5) / self.viewport.width as f32 * 2.0 - 1.0;
+        let ndc_y =
+            1.0 - (y as f32 - self.viewport.y as f32 + 0.5) / self.viewport.height as f32 * 2.0;
+
+        self.camera.unproject(ndc_x, ndc_y)
+    }
+
+    pub fn pick<'a, MS>(&self, meshes: MS, x: i32, y: i32) -> Option<PickHit>
+    where
+        MS: IntoIterator<Item = &'a K3dMesh<'a>>,
+    {
+        self.screen_to_ray(x, y).pick(meshes)
+    }
+
+    fn transform_cached(
+        &self,
+        cache: &mut [CachedVertex],
+        index: usize,
+        vertices: &[[f32; 3]],
+        transform: &Transform,
+    ) -> Option<Point3<i32>> {
+        match cache.get_mut(index) {
+            Some(CachedVertex::Visible(point)) => Some(*point),
+            Some(CachedVertex::Clipped) => None,
+            Some(slot) => {
+                let point = self.transform_point(&vertices[index], transform);
+                *slot = match point {
+                    Some(point) => CachedVertex::Visible(point),
+                    None => CachedVertex::Clipped,
+                };
+                point
+            }
+            None => self.transform_point(&vertices[index], transform),
+        }
+    }
+
     fn transform_points<const N: usize>(
         &self,
+        cache: &mut [CachedVertex],
         indices: &[usize; N],
         vertices: &[[f32; 3]],
-        model_matrix: Matrix4<f32>,
+        transform: &Transform,
     ) -> Option<[Point3<i32>; N]> {
         let mut ret = [Point3::new(0, 0, 0); N];
 
         for i in 0..N {
-            ret[i] = self.transform_point(&vertices[indices[i]], model_matrix)?;
+            ret[i] = self.transform_cached(cache, indices[i], vertices, transform)?;
         }
 
         Some(ret)
     }
 
-    pub fn render<'a, MS, F>(&self, meshes: MS, mut callback: F)
+    fn to_pixel(point: &Point3<i32>) -> Point2<i32> {
+        Point2::new(point.x >> SUBPIXEL_BITS, point.y >> SUBPIXEL_BITS)
+    }
+
+    /// Twice the signed area of a triangle in sub-pixels, negative when it is counter-clockwise.
+    pub(crate) fn signed_area(points: &[SubpixelPoint; 3]) -> i64 {
+        let [a, b, c] = points.map(|p| Point2::new(p.x as i64, p.y as i64));
+        (b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x)
+    }
+
+    fn point_primitive(
+        &self,
+        mesh: &K3dMesh,
+        point: Point2<i32>,
+        size: u8,
+        color: Rgb565,
+    ) -> DrawPrimitive {
+        if size > 1 {
+            DrawPrimitive::PointSprite(point, color, size, mesh.point_shape, self.viewport)
+        } else {
+            DrawPrimitive::ColoredPoint(point, color)
+        }
+    }
+
+    fn is_in_frustum(
+        &self,
+        frustum: &Frustum,
+        mesh: &K3dMesh,
+        model_matrix: &Matrix4<f32>,
+    ) -> bool {
+        if !frustum.intersects_sphere(&mesh.bounding_sphere.transformed(model_matrix)) {
+            return false;
+        }
+
+        let corners = mesh
+            .aabb
+            .corners()
+            .map(|corner| model_matrix.transform_point(&corner));
+
+        frustum.intersects_points(&corners)
+    }
+
+    pub fn render<'a, MS, F>(&self, meshes: MS, callback: F) -> RenderStats
+    where
+        MS: IntoIterator<Item = &'a K3dMesh<'a>>,
+        F: FnMut(DrawPrimitive),
+    {
+        self.render_with_cache(meshes, &mut [], callback)
+    }
+
+    /// Like [`K3dengine::render`], but every vertex is transformed once per mesh using `cache`
+    /// as scratch space. Meshes with more vertices than `cache` can hold are rendered uncached.
+    pub fn render_with_cache<'a, MS, F>(
+        &self,
+        meshes: MS,
+        cache: &mut [CachedVertex],
+        callback: F,
+    ) -> RenderStats
+    where
+        MS: IntoIterator<Item = &'a K3dMesh<'a>>,
+        F: FnMut(DrawPrimitive),
+    {
+        self.render_instances(
+            meshes.into_iter().map(|mesh| (mesh, mesh.model_matrix)),
+            cache,
+            callback,
+        )
+    }
+
+    /// Renders the meshes of `scene`, each one transformed by its model matrix and then by the
+    /// world transform of its node.
+    pub fn render_scene<F, const N: usize>(&self, scene: &Scene<N>, callback: F) -> RenderStats
+    where
+        F: FnMut(DrawPrimitive),
+    {
+        self.render_instances(scene.meshes(), &mut [], callback)
+    }
+
+    fn render_instances<'a, I, F>(
+        &self,
+        instances: I,
+        cache: &mut [CachedVertex],
+        mut callback: F,
+    ) -> RenderStats
+    where
+        I: IntoIterator<Item = (&'a K3dMesh<'a>, Matrix4<f32>)>,
+        F: FnMut(DrawPrimitive),
+    {
+        let frustum = self.camera.frustum();
+        let mut stats = RenderStats::default();
+        let mut translucent: Vec<(f32, &K3dMesh, Matrix4<f32>), MAX_TRANSLUCENT_MESHES> =
+            Vec::new();
+
+        for (mesh, model_matrix) in instances {
+            if mesh.is_translucent()
+                && translucent
+                    .push((self.view_distance(mesh, &model_matrix), mesh, model_matrix))
+                    .is_ok()
+            {
+                continue;
+            }
+
+            self.render_mesh(
+                (mesh, &model_matrix),
+                &frustum,
+                cache,
+                DepthSortKey::Average,
+                &mut stats,
+                &mut |primitive, _| callback(primitive),
+            );
+        }
+
+        // translucent meshes go after the opaque ones, back to front
+        translucent.sort_unstable_by(|a, b| b.0.total_cmp(&a.0));
+        for (_, mesh, model_matrix) in translucent {
+            self.render_mesh(
+                (mesh, &model_matrix),
+                &frustum,
+                cache,
+                DepthSortKey::Average,
+                &mut stats,
+                &mut |primitive, _| callback(primitive),
+            );
+        }
+
+        stats
+    }
+
+    fn view_distance(&self, mesh: &K3dMesh, model_matrix: &Matrix4<f32>) -> f32 {
+        nalgebra::distance_squared(
+            &self.camera.position,
+            &mesh.bounding_sphere.transformed(model_matrix).center,
+        )
+    }
+
+    /// Painter's algorithm: the primitives of all meshes are collected into `buffer`, sorted
+    /// back-to-front and only then passed to `callback`.
+    pub fn render_depth_sorted<'a, MS, F, const N: usize>(
+        &self,
+        meshes: MS,
+        cache: &mut [CachedVertex],
+        buffer: &mut Vec<DepthSortedPrimitive, N>,
+        settings: DepthSortSettings,
+        mut callback: F,
+    ) -> RenderStats
     where
         MS: IntoIterator<Item = &'a K3dMesh<'a>>,
         F: FnMut(DrawPrimitive),
     {
+        let frustum = self.camera.frustum();
+        let mut stats = RenderStats::default();
+        let mut dropped = 0;
+
+        buffer.clear();
+        for mesh in meshes {
+            self.render_mesh(
+                (mesh, &mesh.model_matrix),
+                &frustum,
+                cache,
+                settings.key,
+                &mut stats,
+                &mut |primitive, depth| {
+                    if !depth_sort::push(
+                        buffer,
+                        DepthSortedPrimitive { depth, primitive },
+                        settings.overflow,
+                        &mut callback,
+                    ) {
+                        dropped += 1;
+                    }
+                },
+            );
+        }
+        depth_sort::flush(buffer, &mut callback);
+
+        stats.primitives_dropped = dropped;
+        stats
+    }
+
+    /// Renders the frame in horizontal bands as tall as `band`, for when a full framebuffer does
+    /// not fit in memory. Primitives are binned once into `bins`, then for each band `band` is
+    /// cleared, the overlapping primitives are drawn into it and `flush` is called with the row
+    /// the band starts at. Blended primitives are drawn opaque, see
+    /// [`K3dengine::render_banded_blended`].
+    pub fn render_banded<'a, MS, B, F, const N: usize>(
+        &self,
+        meshes: MS,
+        cache: &mut [CachedVertex],
+        bins: &mut Vec<BinnedPrimitive, N>,
+        band: &mut B,
+        clear_color: Rgb565,
+        flush: F,
+    ) -> RenderStats
+    where
+        MS: IntoIterator<Item = &'a K3dMesh<'a>>,
+        B: DrawTarget,
+        <B as DrawTarget>::Error: Debug,
+        B::Color: From<Rgb565> + DitherColor,
+        F: FnMut(&mut B, u16),
+    {
+        self.render_bands(
+            meshes,
+            cache,
+            bins,
+            (band, clear_color.into()),
+            flush,
+            |primitive, target| draw::draw(primitive, target),
+        )
+    }
+
+    /// Like [`K3dengine::render_banded`], blending translucent meshes and anti-aliased lines
+    /// with what the band already contains.
+    pub fn render_banded_blended<'a, MS, B, F, const N: usize>(
+        &self,
+        meshes: MS,
+        cache: &mut [CachedVertex],
+        bins: &mut Vec<BinnedPrimitive, N>,
+        band: &mut B,
+        clear_color: Rgb565,
+        flush: F,
+    ) -> RenderStats
+    where
+        MS: IntoIterator<Item = &'a K3dMesh<'a>>,
+        B: BlendTarget,
+        <B as DrawTarget>::Error: Debug,
+        F: FnMut(&mut B, u16),
+    {
+        self.render_bands(
+            meshes,
+            cache,
+            bins,
+            (band, clear_color),
+            flush,
+            |primitive, target| draw::draw_blended(primitive, target),
+        )
+    }
+
+    fn render_bands<'a, MS, B, F, P, const N: usize>(
+        &self,
+        meshes: MS,
+        cache: &mut [CachedVertex],
+        bins: &mut Vec<BinnedPrimitive, N>,
+        (band, clear_color): (&mut B, B::Color),
+        mut flush: F,
+        mut draw_primitive: P,
+    ) -> RenderStats
+    where
+        MS: IntoIterator<Item = &'a K3dMesh<'a>>,
+        B: DrawTarget,
+        <B as DrawTarget>::Error: Debug,
+        F: FnMut(&mut B, u16),
+        P: FnMut(DrawPrimitive, &mut BandTarget<'_, B>),
+    {
+        let mut dropped = 0;
+
+        bins.clear();
+        let mut stats = self.render_with_cache(meshes, cache, |primitive| {
+            if bins.push(BinnedPrimitive::new(primitive)).is_err() {
+                dropped += 1;
+            }
+        });
+        stats.primitives_dropped = dropped;
+
+        let band_height = band.bounding_box().size.height.max(1) as i32;
+        let screen_size = Size::new(self.width as u32, self.height as u32);
+
+        for y in (0..self.height as i32).step_by(band_height as usize) {
+            band.clear(clear_color).unwrap();
+
+            let mut target = BandTarget::new(band, y, screen_size);
+            for bin in bins.iter().filter(|bin| bin.overlaps_rows(y, band_height)) {
+                draw_primitive(bin.primitive, &mut target);
+            }
+
+            flush(band, y as u16);
+        }
+
+        stats
+    }
+
+    /// Draws the faces of `meshes` straight into `target`, running `vertex_shader` on the corners
+    /// of every face and `fragment_shader` on every pixel they cover, with perspective-correct
+    /// varyings. Render modes are ignored, back faces are culled and faces are drawn in order
+    /// without depth test.
+    pub fn render_shaded<'a, MS, VS, FS, D>(
+        &self,
+        meshes: MS,
+        vertex_shader: &VS,
+        fragment_shader: &FS,
+        target: &mut D,
+    ) -> RenderStats
+    where
+        MS: IntoIterator<Item = &'a K3dMesh<'a>>,
+        VS: VertexShader,
+        FS: FragmentShader<VS::Varying>,
+        D: DrawTarget,
+        <D as DrawTarget>::Error: Debug,
+        D::Color: From<Rgb565>,
+    {
+        let frustum = self.camera.frustum();
+        let mut stats = RenderStats::default();
+
         for mesh in meshes {
             if mesh.geometry.vertices.is_empty() {
                 continue;
             }
 
-            let transform_matrix = self.camera.vp_matrix * mesh.model_matrix;
+            if !self.is_in_frustum(&frustum, mesh, &mesh.model_matrix) {
+                stats.meshes_culled += 1;
+                stats.faces_culled += mesh.geometry.faces.len();
+                continue;
+            }
+            stats.meshes_rendered += 1;
+
+            let mvp = self.camera.vp_matrix * mesh.model_matrix;
+            let normal_matrix = mesh.normal_matrix();
 
-            match mesh.render_mode {
-                RenderMode::Points => {
-                    let screen_space_points = mesh
-                        .geometry
-                        .vertices
-                        .iter()
-                        .filter_map(|v| self.transform_point(v, transform_matrix));
+            for (i, face) in mesh.geometry.faces.iter().enumerate() {
+                let normal = mesh.geometry.normals.get(i).map(|n| Vector3::from(*n));
+                let vertex_normals = mesh.geometry.vertex_normals.get(i);
 
-                    if mesh.geometry.colors.len() == mesh.geometry.vertices.len() {
-                        for (point, color) in screen_space_points.zip(mesh.geometry.colors) {
-                            callback(DrawPrimitive::ColoredPoint(point.xy(), *color));
-                        }
-                    } else {
-                        for point in screen_space_points {
-                            callback(DrawPrimitive::ColoredPoint(point.xy(), mesh.color));
-                        }
-                    }
+                let [Some(a), Some(b), Some(c)] = core::array::from_fn(|corner| {
+                    let index = face[corner];
+                    let input = VertexInput {
+                        mesh,
+                        index,
+                        position: Point3::from(mesh.geometry.vertices[index]),
+                        normal,
+                        vertex_normal: vertex_normals.map(|n| Vector3::from(n[corner])),
+                        color: mesh.geometry.colors.get(index).copied(),
+                        mvp,
+                        normal_matrix,
+                    };
+                    self.project(vertex_shader.vertex(&input))
+                }) else {
+                    continue;
+                };
+
+                let vertices = [a, b, c];
+                if mesh.cull_mode.culls(shader::signed_area(&vertices)) {
+                    stats.backfaces_culled += 1;
+                    continue;
                 }
 
-                RenderMode::Lines if !mesh.geometry.lines.is_empty() => {
-                    for line in mesh.geometry.lines {
-                        if let Some([p1, p2]) =
-                            self.transform_points(line, mesh.geometry.vertices, transform_matrix)
-                        {
-                            callback(DrawPrimitive::Line([p1.xy(), p2.xy()], mesh.color));
-                        }
+                shader::rasterize(&vertices, &self.viewport, fragment_shader, target);
+            }
+        }
+
+        stats
+    }
+
+    fn project<V>(&self, (clip, varying): (Vector4<f32>, V)) -> Option<ProjectedVertex<V>> {
+        if clip.w < self.camera.near || clip.w > self.camera.far {
+            return None;
+        }
+        let inv_w = 1.0 / clip.w;
+
+        Some(ProjectedVertex {
+            x: self.viewport.x as f32 + (1.0 + clip.x * inv_w) * 0.5 * self.viewport.width as f32,
+            y: self.viewport.y as f32 + (1.0 - clip.y * inv_w) * 0.5 * self.viewport.height as f32,
+            depth: clip.z * inv_w * 0.5 + 0.5,
+            w: clip.w,
+            varying,
+        })
+    }
+
+    fn render_mesh<F>(
+        &self,
+        (mesh, model_matrix): (&K3dMesh, &Matrix4<f32>),
+        frustum: &Frustum,
+        cache: &mut [CachedVertex],
+        key: DepthSortKey,
+        stats: &mut RenderStats,
+        emit: &mut F,
+    ) where
+        F: FnMut(DrawPrimitive, i32),
+    {
+        let blend = mesh.blend();
+        let emit = &mut |primitive: DrawPrimitive, depth| {
+            if blend.is_opaque() {
+                emit(primitive, depth)
+            } else {
+                emit(primitive.with_blend(blend), depth)
+            }
+        };
+
+        if mesh.geometry.vertices.is_empty() {
+            return;
+        }
+
+        if !self.is_in_frustum(frustum, mesh, model_matrix) {
+            stats.meshes_culled += 1;
+            stats.faces_culled += mesh.geometry.faces.len();
+            return;
+        }
+        stats.meshes_rendered += 1;
+
+        let cache = match cache.get_mut(..mesh.geometry.vertices.len()) {
+            Some(cache) => {
+                cache.fill(CachedVertex::Empty);
+                cache
+            }
+            None => &mut [],
+        };
+
+        let transform = self.mesh_transform(model_matrix);
+
+        match mesh.render_mode {
+            RenderMode::Points => {
+                let colors = mesh.geometry.colors.len() == mesh.geometry.vertices.len();
+                let attenuation = mesh.point_attenuation.map(|reference_distance| {
+                    PointAttenuation::new(
+                        &self.camera,
+                        model_matrix,
+                        mesh.point_size,
+                        reference_distance,
+                    )
+                });
+
+                for (i, vertex) in mesh.geometry.vertices.iter().enumerate() {
+                    let Some(point) = self.transform_point(vertex, &transform) else {
+                        continue;
+                    };
+                    let pixel = Self::to_pixel(&point);
+                    if !self.viewport.contains(pixel.x, pixel.y) {
+                        continue;
                     }
+                    let color = if colors {
+                        mesh.geometry.colors[i]
+                    } else {
+                        mesh.color
+                    };
+
+                    emit(
+                        self.point_primitive(
+                            mesh,
+                            pixel,
+                            attenuation
+                                .as_ref()
+                                .map_or(mesh.point_size, |attenuation| attenuation.size(vertex)),
+                            color,
+                        ),
+                        point.z,
+                    );
                 }
+            }
 
-                RenderMode::Lines if !mesh.geometry.faces.is_empty() => {
-                    for face in mesh.geometry.faces {
-                        if let Some([p1, p2, p3]) =
-                            self.transform_points(face, mesh.geometry.vertices, transform_matrix)
-                        {
-                            callback(DrawPrimitive::Line([p1.xy(), p2.xy()], mesh.color));
-                            callback(DrawPrimitive::Line([p2.xy(), p3.xy()], mesh.color));
-                            callback(DrawPrimitive::Line([p3.xy(), p1.xy()], mesh.color));
-                        }
+            RenderMode::Lines if !mesh.geometry.lines.is_empty() => {
+                for line in mesh.geometry.lines {
+                    if let Some([p1, p2]) =
+                        self.transform_points(cache, line, mesh.geometry.vertices, &transform)
+                    {
+                        emit(
+                            mesh.line_primitive(
+                                [Self::to_pixel(&p1), Self::to_pixel(&p2)],
+                                self.viewport,
+                            ),
+                            key.depth(&[p1.z, p2.z]),
+                        );
                     }
                 }
+            }
 
-                RenderMode::Lines => {}
+            RenderMode::Lines if !mesh.geometry.faces.is_empty() => {
+                for face in mesh.geometry.faces {
+                    if let Some([p1, p2, p3]) =
+                        self.transform_points(cache, face, mesh.geometry.vertices, &transform)
+                    {
+                        let depth = key.depth(&[p1.z, p2.z, p3.z]);
+                        emit(
+                            mesh.line_primitive(
+                                [Self::to_pixel(&p1), Self::to_pixel(&p2)],
+                                self.viewport,
+                            ),
+                            depth,
+                        );
+                        emit(
+                            mesh.line_primitive(
+                                [Self::to_pixel(&p2), Self::to_pixel(&p3)],
+                                self.viewport,
+                            ),
+                            depth,
+                        );
+                        emit(
+                            mesh.line_primitive(
+                                [Self::to_pixel(&p3), Self::to_pixel(&p1)],
+                                self.viewport,
+                            ),
+                            depth,
+                        );
+                    }
+                }
+            }
 
-                RenderMode::SolidLightDir(direction) => {
-                    for (face, normal) in mesh.geometry.faces.iter().zip(mesh.geometry.normals) {
-                        //Backface culling
-                        let normal = Vector3::new(normal[0], normal[1], normal[2]);
+            RenderMode::Lines => {}
 
-                        let transformed_normal = mesh.model_matrix.transform_vector(&normal);
+            RenderMode::SolidLightDir(direction) => {
+                let normals = FaceNormals::new(model_matrix);
+                let lighting = Lighting::new(direction);
 
-                        if self.camera.get_direction().dot(&transformed_normal) < 0.0 {
+                for (i, (face, normal)) in mesh
+                    .geometry
+                    .faces
+                    .iter()
+                    .zip(mesh.geometry.normals)
+                    .enumerate()
+                {
+                    if let Some([p1, p2, p3]) =
+                        self.transform_points(cache, face, mesh.geometry.vertices, &transform)
+                    {
+                        let points = [p1, p2, p3].map(|p| SubpixelPoint::new(p.x, p.y));
+                        if mesh.cull_mode.culls(Self::signed_area(&points)) {
+                            stats.backfaces_culled += 1;
                             continue;
                         }
 
-                        if let Some([p1, p2, p3]) =
-                            self.transform_points(face, mesh.geometry.vertices, transform_matrix)
-                        {
-                            let color_as_float = Vector3::new(
-                                mesh.color.r() as f32 / 32.0,
-                                mesh.color.g() as f32 / 64.0,
-                                mesh.color.b() as f32 / 32.0,
-                            );
-
-                            let mut final_color = Vector3::new(0.0f32, 0.0, 0.0);
-
-                            let intensity = transformed_normal.dot(&direction);
-
-                            let intensity = intensity.max(0.0);
-
-                            final_color += color_as_float * intensity + color_as_float * 0.4;
-
-                            let final_color = Vector3::new(
-                                final_color.x.clamp(0.0, 1.0),
-                                final_color.y.clamp(0.0, 1.0),
-                                final_color.z.clamp(0.0, 1.0),
-                            );
-
-                            let color = Rgb565::new(
-                                (final_color.x * 31.0) as u8,
-                                (final_color.y * 63.0) as u8,
-                                (final_color.z * 31.0) as u8,
-                            );
-                            callback(DrawPrimitive::ColoredTriangle(
-                                [p1.xy(), p2.xy(), p3.xy()],
-                                color,
-                            ));
-                        }
+                        let normal = normals.world(normal);
+                        let color = mesh.geometry.face_colors.get(i).copied();
+                        let color = color.unwrap_or(mesh.color);
+                        let primitive = if mesh.dither == Dither::None {
+                            DrawPrimitive::ColoredTriangle(
+                                points,
+                                lighting.rgb565(&normal, color),
+                                self.viewport,
+                            )
+                        } else {
+                            DrawPrimitive::ShadedTriangle(
+                                points,
+                                lighting.rgb888(&normal, color),
+                                mesh.dither,
+                                self.viewport,
+                            )
+                        };
+                        emit(primitive, key.depth(&[p1.z, p2.z, p3.z]));
                     }
                 }
+            }
 
-                RenderMode::Solid => {
-                    if mesh.geometry.normals.is_empty() {
-                        for face in mesh.geometry.faces.iter() {
-                            if let Some([p1, p2, p3]) = self.transform_points(
-                                face,
-                                mesh.geometry.vertices,
-                                transform_matrix,
-                            ) {
-                                callback(DrawPrimitive::ColoredTriangle(
-                                    [p1.xy(), p2.xy(), p3.xy()],
-                                    mesh.color,
-                                ));
-                            }
-                        }
-                    } else {
-                        for (face, normal) in mesh.geometry.faces.iter().zip(mesh.geometry.normals)
-                        {
-                            //Backface culling
-                            let normal = Vector3::new(normal[0], normal[1], normal[2]);
-
-                            let transformed_normal = mesh.model_matrix.transform_vector(&normal);
-
-                            if self.camera.get_direction().dot(&transformed_normal) < 0.0 {
-                                continue;
-                            }
-
-                            if let Some([p1, p2, p3]) = self.transform_points(
-                                face,
-                                mesh.geometry.vertices,
-                                transform_matrix,
-                            ) {
-                                callback(DrawPrimitive::ColoredTriangle(
-                                    [p1.xy(), p2.xy(), p3.xy()],
-                                    mesh.color,
-                                ));
-                            }
+            RenderMode::Solid => {
+                for (i, face) in mesh.geometry.faces.iter().enumerate() {
+                    if let Some([p1, p2, p3]) =
+                        self.transform_points(cache, face, mesh.geometry.vertices, &transform)
+                    {
+                        let points = [p1, p2, p3].map(|p| SubpixelPoint::new(p.x, p.y));
+                        if mesh.cull_mode.culls(Self::signed_area(&points)) {
+                            stats.backfaces_culled += 1;
+                            continue;
                         }
+
+                        let color = mesh.geometry.face_colors.get(i).copied();
+                        emit(
+                            DrawPrimitive::ColoredTriangle(
+                                points,
+                                color.unwrap_or(mesh.color),
+                                self.viewport,
+                            ),
+                            key.depth(&[p1.z, p2.z, p3.z]),
+                        );
                     }
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use embedded_graphics_core::pixelcolor::{IntoStorage, RgbColor};
+
+    use super::*;
+    use crate::framebuffer::DmaReadyFramebuffer;
+    use crate::mesh::CullMode;
+    use crate::mesh::primitives::{MeshStorage, plane};
+
+    #[test]
+    fn meshes_straddling_the_viewport_are_clipped_not_dropped() {
+        let mut storage = MeshStorage::<25, 32, 40>::new();
+        // 4x4 cells, the outer vertices are off-screen
+        let geometry = plane(storage.builder(), 6.0, 6.0, 4, 4).unwrap();
+        let mut mesh = K3dMesh::new(geometry);
+        mesh.set_attitude(core::f32::consts::FRAC_PI_2, 0.0, 0.0);
+        mesh.set_render_mode(RenderMode::Solid);
+        mesh.set_cull_mode(CullMode::None);
+
+        let mut engine = K3dengine::new(32, 32);
+        engine.camera.set_position(Point3::new(0.0, 0.0, 2.0));
+        engine.camera.set_target(Point3::new(0.0, 0.0, 0.0));
+        engine.set_viewport(Viewport::new(8, 8, 16, 16));
+
+        let mut rows = [[0u16; 32]; 32];
+        let mut framebuffer = DmaReadyFramebuffer::from_array(&mut rows, false);
+        engine.render([&mesh], |primitive| draw::draw(primitive, &mut framebuffer));
+
+        for (y, row) in rows.iter().enumerate() {
+            for (x, pixel) in row.iter().enumerate() {
+                let inside = (8..24).contains(&x) && (8..24).contains(&y);
+                let lit = *pixel == Rgb565::WHITE.into_storage();
+                assert_eq!(lit, inside, "pixel {x}, {y}");
+            }
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use embedded_graphics_core::pixelcolor::{Rgb565, Rgb888, RgbColor};
+#[cfg(not(feature = "fixed-point"))]
+use nalgebra::Matrix3;
+use nalgebra::{Matrix4, Vector3};
+
+#[cfg(feature = "fixed-point")]
+use crate::fixed::{self, Fixed, FixedMatrix4};
+use crate::mesh::normal_matrix;
+
+/// Fraction of the color kept by faces that are not lit.
+const AMBIENT: f32 = 0.4;
+
+/// Transforms face normals to world space with the normal matrix of the model.
+#[cfg(not(feature = "fixed-point"))]
+pub(crate) struct FaceNormals {
+    normal_matrix: Matrix3<f32>,
+}
+
+#[cfg(not(feature = "fixed-point"))]
+impl FaceNormals {
+    pub fn new(model_matrix: &Matrix4<f32>) -> FaceNormals {
+        FaceNormals {
+            normal_matrix: normal_matrix(model_matrix),
+        }
+    }
+
+    pub fn world(&self, normal: &[f32; 3]) -> Vector3<f32> {
+        let normal = self.normal_matrix * Vector3::new(normal[0], normal[1], normal[2]);
+
+        normal.try_normalize(0.0).unwrap_or(normal)
+    }
+}
+
+/// Per-face lighting by a directional light, created once per mesh and applied to the color of
+/// each face.
+#[cfg(not(feature = "fixed-point"))]
+pub(crate) struct Lighting {
+    direction: Vector3<f32>,
+}
+
+#[cfg(not(feature = "fixed-point"))]
+impl Lighting {
+    pub fn new(direction: Vector3<f32>) -> Lighting {
+        Lighting { direction }
+    }
+
+    fn shade(&self, normal: &Vector3<f32>, color: Rgb565) -> Vector3<f32> {
+        let intensity = normal.dot(&self.direction).max(0.0);
+        let color = Vector3::new(
+            color.r() as f32 / 32.0,
+            color.g() as f32 / 64.0,
+            color.b() as f32 / 32.0,
+        );
+
+        (color * intensity + color * AMBIENT).map(|c| c.clamp(0.0, 1.0))
+    }
+
+    pub fn rgb565(&self, normal: &Vector3<f32>, color: Rgb565) -> Rgb565 {
+        let color = self.shade(normal, color);
+        Rgb565::new(
+            (color.x * 31.0) as u8,
+            (color.y * 63.0) as u8,
+            (color.z * 31.0) as u8,
+        )
+    }
+
+    pub fn rgb888(&self, normal: &Vector3<f32>, color: Rgb565) -> Rgb888 {
+        let color = self.shade(normal, color) * 255.0;
+        Rgb888::new(color.x as u8, color.y as u8, color.z as u8)
+    }
+}
+
+#[cfg(feature = "fixed-point")]
+const AMBIENT_FIXED: Fixed = Fixed((AMBIENT * (1 << Fixed::FRAC_BITS) as f32) as i32);
+
+#[cfg(feature = "fixed-point")]
+pub(crate) struct FaceNormals {
+    normal_matrix: FixedMatrix4,
+}
+
+#[cfg(feature = "fixed-point")]
+impl FaceNormals {
+    pub fn new(model_matrix: &Matrix4<f32>) -> FaceNormals {
+        FaceNormals {
+            normal_matrix: FixedMatrix4::from_matrix(&normal_matrix(model_matrix).to_homogeneous()),
+        }
+    }
+
+    pub fn world(&self, normal: &[f32; 3]) -> [Fixed; 3] {
+        fixed::normalize(&self.normal_matrix.transform_vector(&fixed::vector(normal)))
+    }
+}
+
+#[cfg(feature = "fixed-point")]
+pub(crate) struct Lighting {
+    direction: [Fixed; 3],
+}
+
+#[cfg(feature = "fixed-point")]
+impl Lighting {
+    pub fn new(direction: Vector3<f32>) -> Lighting {
+        Lighting {
+            direction: fixed::vector(&direction.into()),
+        }
+    }
+
+    fn shade(&self, normal: &[Fixed; 3], color: Rgb565) -> [Fixed; 3] {
+        let intensity = fixed::dot(normal, &self.direction).max(Fixed::ZERO) + AMBIENT_FIXED;
+        let color = [
+            Fixed(((color.r() as i32) << Fixed::FRAC_BITS) / 32),
+            Fixed(((color.g() as i32) << Fixed::FRAC_BITS) / 64),
+            Fixed(((color.b() as i32) << Fixed::FRAC_BITS) / 32),
+        ];
+
+        color.map(|c| (c * intensity).clamp(Fixed::ZERO, Fixed::ONE))
+    }
+
+    pub fn rgb565(&self, normal: &[Fixed; 3], color: Rgb565) -> Rgb565 {
+        let [r, g, b] = self.shade(normal, color);
+        Rgb565::new(
+            r.mul_int(31) as u8,
+            g.mul_int(63) as u8,
+            b.mul_int(31) as u8,
+        )
+    }
+
+    pub fn rgb888(&self, normal: &[Fixed; 3], color: Rgb565) -> Rgb888 {
+        let [r, g, b] = self.shade(normal, color).map(|c| c.mul_int(255) as u8);
+        Rgb888::new(r, g, b)
+    }
+}
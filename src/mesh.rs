@@ -1,7 +1,59 @@
-use embedded_graphics_core::pixelcolor::{Rgb565, WebColors};
+use embedded_graphics_core::pixelcolor::WebColors;
 use heapless::Vec;
 use log::error;
-use nalgebra::{Point3, Similarity3, UnitQuaternion, Vector3};
+
+use crate::DrawPrimitive;
+use crate::Viewport;
+use crate::blend::{Blend, BlendMode};
+use crate::dither::Dither;
+use nalgebra::{Isometry3, Matrix3, Matrix4, Point2, Point3, UnitQuaternion, Vector3};
+
+pub mod primitives;
+
+/// Re-exported next to [`Geometry`] for the face colors generated by `load_stl::embed_stl!`.
+pub use embedded_graphics_core::pixelcolor::Rgb565;
+
+use primitives::CapacityError;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Antialias {
+    #[default]
+    Off,
+    /// Blend the line edges with what is already in the framebuffer, needs
+    /// [`crate::draw::draw_blended`].
+    Blend,
+    /// Blend the line edges with a known background color, works on any draw target.
+    Background(Rgb565),
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PointShape {
+    #[default]
+    Square,
+    Round,
+}
+
+/// Faces discarded by their winding order on screen, faces are counter-clockwise when seen from
+/// the front.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CullMode {
+    #[default]
+    Back,
+    Front,
+    None,
+}
+
+impl CullMode {
+    /// `signed_area` is negative for counter-clockwise triangles, screen y going down. Degenerate
+    /// triangles are culled unless culling is disabled.
+    pub(crate) fn culls(self, signed_area: i64) -> bool {
+        match self {
+            CullMode::Back => signed_area >= 0,
+            CullMode::Front => signed_area <= 0,
+            CullMode::None => false,
+        }
+    }
+}
 
 #[derive(Debug, PartialEq)]
 pub enum RenderMode {
@@ -10,13 +62,74 @@ pub enum RenderMode {
     Solid,
     SolidLightDir(Vector3<f32>),
 }
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Point3<f32>,
+    pub radius: f32,
+}
+
+/// Inverse transpose of the linear part of `model_matrix`, transforms normals so that they stay
+/// perpendicular to the faces under non-uniform scaling.
+pub fn normal_matrix(model_matrix: &Matrix4<f32>) -> Matrix3<f32> {
+    let linear = model_matrix.fixed_view::<3, 3>(0, 0).into_owned();
+
+    linear
+        .try_inverse()
+        .map(|inverse| inverse.transpose())
+        .unwrap_or(linear)
+}
+
+impl BoundingSphere {
+    /// Sphere containing this one once transformed by `matrix`.
+    pub fn transformed(&self, matrix: &Matrix4<f32>) -> BoundingSphere {
+        let scale = (0..3)
+            .map(|i| matrix.fixed_view::<3, 1>(0, i).norm())
+            .fold(0.0f32, f32::max);
+
+        BoundingSphere {
+            center: matrix.transform_point(&self.center),
+            radius: self.radius * scale,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    pub fn center(&self) -> Point3<f32> {
+        nalgebra::center(&self.min, &self.max)
+    }
+
+    pub fn corners(&self) -> [Point3<f32>; 8] {
+        let (min, max) = (self.min, self.max);
+        [
+            Point3::new(min.x, min.y, min.z),
+            Point3::new(max.x, min.y, min.z),
+            Point3::new(min.x, max.y, min.z),
+            Point3::new(max.x, max.y, min.z),
+            Point3::new(min.x, min.y, max.z),
+            Point3::new(max.x, min.y, max.z),
+            Point3::new(min.x, max.y, max.z),
+            Point3::new(max.x, max.y, max.z),
+        ]
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct Geometry<'a> {
     pub vertices: &'a [[f32; 3]],
     pub faces: &'a [[usize; 3]],
     pub colors: &'a [Rgb565],
+    /// One color per face, used instead of the mesh color by the solid render modes.
+    pub face_colors: &'a [Rgb565],
     pub lines: &'a [[usize; 2]],
     pub normals: &'a [[f32; 3]],
+    /// One normal per corner of each face, for smooth shading.
+    pub vertex_normals: &'a [[[f32; 3]; 3]],
 }
 
 impl Geometry<'_> {
@@ -48,9 +161,49 @@ impl Geometry<'_> {
             return false;
         }
 
+        if !self.face_colors.is_empty() && self.face_colors.len() != self.faces.len() {
+            error!("Face colors are not the same length as faces");
+            return false;
+        }
+
+        if !self.vertex_normals.is_empty() && self.vertex_normals.len() != self.faces.len() {
+            error!("Vertex normals are not the same length as faces");
+            return false;
+        }
+
         true
     }
 
+    pub fn aabb(&self) -> Aabb {
+        let Some(first) = self.vertices.first() else {
+            return Aabb {
+                min: Point3::origin(),
+                max: Point3::origin(),
+            };
+        };
+
+        let mut min = Point3::new(first[0], first[1], first[2]);
+        let mut max = min;
+        for v in self.vertices {
+            let v = Point3::new(v[0], v[1], v[2]);
+            min = min.inf(&v);
+            max = max.sup(&v);
+        }
+
+        Aabb { min, max }
+    }
+
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        let center = self.aabb().center();
+        let radius = self
+            .vertices
+            .iter()
+            .map(|v| nalgebra::distance(&center, &Point3::new(v[0], v[1], v[2])))
+            .fold(0.0f32, f32::max);
+
+        BoundingSphere { center, radius }
+    }
+
     pub fn lines_from_faces(faces: &[[usize; 3]]) -> Vec<(usize, usize), 512> {
         let mut lines: Vec<(usize, usize), 512> = Vec::new();
         for face in faces {
@@ -67,26 +220,125 @@ impl Geometry<'_> {
         }
         lines
     }
+
+    /// Writes the normal of each face to `normals`, from the counter-clockwise winding order.
+    /// Degenerate faces get a zero normal.
+    pub fn compute_face_normals(
+        vertices: &[[f32; 3]],
+        faces: &[[usize; 3]],
+        normals: &mut [[f32; 3]],
+    ) -> Result<(), CapacityError> {
+        if normals.len() < faces.len() {
+            return Err(CapacityError);
+        }
+
+        for (face, normal) in faces.iter().zip(normals) {
+            let n = area_normal(vertices, face);
+            *normal = n.try_normalize(0.0).unwrap_or(n).into();
+        }
+        Ok(())
+    }
+
+    /// Writes a normal for each corner of each face to `normals`, the average of the normals of
+    /// the faces sharing the vertex weighted by their area. Faces meeting at more than
+    /// `crease_angle` radians don't contribute to each other, keeping hard edges, `PI` smooths
+    /// everything. `adjacency` is scratch space for three entries per face, used to sort the
+    /// face corners by vertex so only faces sharing a vertex are compared.
+    pub fn compute_vertex_normals(
+        vertices: &[[f32; 3]],
+        faces: &[[usize; 3]],
+        crease_angle: f32,
+        adjacency: &mut [usize],
+        normals: &mut [[[f32; 3]; 3]],
+    ) -> Result<(), CapacityError> {
+        let corner_count = faces.len() * 3;
+        if normals.len() < faces.len() || adjacency.len() < corner_count {
+            return Err(CapacityError);
+        }
+
+        // corner `c` is corner `c % 3` of face `c / 3`
+        let adjacency = &mut adjacency[..corner_count];
+        for (i, corner) in adjacency.iter_mut().enumerate() {
+            *corner = i;
+        }
+        let vertex = |corner: &usize| faces[corner / 3][corner % 3];
+        adjacency.sort_unstable_by_key(vertex);
+
+        let min_cos = crease_angle.cos();
+        for shared in adjacency.chunk_by(|a, b| vertex(a) == vertex(b)) {
+            for &corner in shared {
+                let face_normal = area_normal(vertices, &faces[corner / 3]);
+                let face_direction = face_normal.try_normalize(0.0);
+
+                let mut sum = face_normal;
+                for &other in shared {
+                    if other / 3 == corner / 3 {
+                        continue;
+                    }
+
+                    let other_normal = area_normal(vertices, &faces[other / 3]);
+                    let smooth = match (face_direction, other_normal.try_normalize(0.0)) {
+                        (Some(a), Some(b)) => a.dot(&b) >= min_cos,
+                        _ => false,
+                    };
+                    if smooth {
+                        sum += other_normal;
+                    }
+                }
+                normals[corner / 3][corner % 3] = sum.try_normalize(0.0).unwrap_or(sum).into();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Normal of a counter-clockwise face, with a length of twice its area.
+fn area_normal(vertices: &[[f32; 3]], face: &[usize; 3]) -> Vector3<f32> {
+    let [a, b, c] = face.map(|i| Vector3::from(vertices[i]));
+    (b - a).cross(&(c - a))
 }
 
 pub struct K3dMesh<'a> {
-    pub similarity: Similarity3<f32>,
-    pub model_matrix: nalgebra::Matrix4<f32>,
+    pub isometry: Isometry3<f32>,
+    pub scale: Vector3<f32>,
+    pub model_matrix: Matrix4<f32>,
 
     pub color: Rgb565,
+    pub opacity: u8,
+    pub blend_mode: BlendMode,
+    pub dither: Dither,
+    pub antialias: Antialias,
+    pub line_width: u8,
+    pub point_size: u8,
+    pub point_shape: PointShape,
+    pub point_attenuation: Option<f32>,
+    pub cull_mode: CullMode,
     pub render_mode: RenderMode,
     pub geometry: Geometry<'a>,
+    pub bounding_sphere: BoundingSphere,
+    pub aabb: Aabb,
 }
 
 impl K3dMesh<'_> {
     pub fn new(geometry: Geometry) -> K3dMesh {
         assert!(geometry.check_validity());
-        let sim = Similarity3::new(Vector3::new(0.0, 0.0, 0.0), nalgebra::zero(), 1.0);
         K3dMesh {
-            model_matrix: sim.to_homogeneous(),
-            similarity: sim,
+            isometry: Isometry3::identity(),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+            model_matrix: Matrix4::identity(),
             color: Rgb565::CSS_WHITE,
+            opacity: u8::MAX,
+            blend_mode: BlendMode::Normal,
+            dither: Dither::None,
+            antialias: Antialias::Off,
+            line_width: 1,
+            point_size: 1,
+            point_shape: PointShape::Square,
+            point_attenuation: None,
+            cull_mode: CullMode::Back,
             render_mode: RenderMode::Points,
+            bounding_sphere: geometry.bounding_sphere(),
+            aabb: geometry.aabb(),
             geometry,
         }
     }
@@ -95,47 +347,245 @@ impl K3dMesh<'_> {
         self.color = color;
     }
 
+    /// 255 is fully opaque. Translucent meshes are drawn after the opaque ones, use
+    /// [`crate::draw::draw_blended`] to actually blend them.
+    pub fn set_opacity(&mut self, opacity: u8) {
+        self.opacity = opacity;
+    }
+
+    pub fn set_blend_mode(&mut self, blend_mode: BlendMode) {
+        self.blend_mode = blend_mode;
+    }
+
+    /// Only affects [`RenderMode::SolidLightDir`], where shading produces colors finer than the
+    /// draw target can show.
+    pub fn set_dither(&mut self, dither: Dither) {
+        self.dither = dither;
+    }
+
+    /// Only affects 1 pixel wide lines of [`RenderMode::Lines`].
+    pub fn set_antialias(&mut self, antialias: Antialias) {
+        self.antialias = antialias;
+    }
+
+    /// Width in pixels of the lines of [`RenderMode::Lines`], wide lines get round caps so that
+    /// connected lines join without gaps.
+    pub fn set_line_width(&mut self, width: u8) {
+        self.line_width = width.max(1);
+    }
+
+    /// Size in pixels of the points of [`RenderMode::Points`].
+    pub fn set_point_size(&mut self, size: u8, shape: PointShape) {
+        self.point_size = size.max(1);
+        self.point_shape = shape;
+    }
+
+    /// Scales the point size with the distance to the camera, points at `reference_distance` are
+    /// drawn with the size given to [`K3dMesh::set_point_size`].
+    pub fn set_point_attenuation(&mut self, reference_distance: Option<f32>) {
+        self.point_attenuation = reference_distance;
+    }
+
+    /// Affects the solid render modes and [`crate::K3dengine::render_shaded`], open or
+    /// double-sided geometry needs [`CullMode::None`].
+    pub fn set_cull_mode(&mut self, cull_mode: CullMode) {
+        self.cull_mode = cull_mode;
+    }
+
+    pub(crate) fn line_primitive(
+        &self,
+        points: [Point2<i32>; 2],
+        viewport: Viewport,
+    ) -> DrawPrimitive {
+        if self.line_width > 1 {
+            return DrawPrimitive::ThickLine(points, self.color, self.line_width, viewport);
+        }
+
+        match self.antialias {
+            Antialias::Off => DrawPrimitive::Line(points, self.color, viewport),
+            Antialias::Blend => DrawPrimitive::AntialiasedLine(points, self.color, None, viewport),
+            Antialias::Background(background) => {
+                DrawPrimitive::AntialiasedLine(points, self.color, Some(background), viewport)
+            }
+        }
+    }
+
+    pub fn blend(&self) -> Blend {
+        Blend::new(self.opacity, self.blend_mode)
+    }
+
+    pub fn is_translucent(&self) -> bool {
+        !self.blend().is_opaque()
+    }
+
     pub fn set_render_mode(&mut self, mode: RenderMode) {
         self.render_mode = mode;
     }
 
     pub fn set_position(&mut self, x: f32, y: f32, z: f32) {
-        self.similarity.isometry.translation.x = x;
-        self.similarity.isometry.translation.y = y;
-        self.similarity.isometry.translation.z = z;
+        self.isometry.translation.x = x;
+        self.isometry.translation.y = y;
+        self.isometry.translation.z = z;
         self.update_model_matrix();
     }
 
     pub fn get_position(&self) -> Point3<f32> {
-        self.similarity.isometry.translation.vector.into()
+        self.model_matrix.transform_point(&Point3::origin())
     }
 
     pub fn set_attitude(&mut self, roll: f32, pitch: f32, yaw: f32) {
-        self.similarity.isometry.rotation = UnitQuaternion::from_euler_angles(roll, pitch, yaw);
+        self.isometry.rotation = UnitQuaternion::from_euler_angles(roll, pitch, yaw);
         self.update_model_matrix();
     }
 
+    /// Rotates the mesh so that its local +Z axis points towards `target`, with +Y kept as close
+    /// to up as possible. Position and scale are kept, a target at the position is ignored.
     pub fn set_target(&mut self, target: Point3<f32>) {
-        let view = Similarity3::look_at_rh(
-            &self.similarity.isometry.translation.vector.into(),
-            &target,
-            &Vector3::y(),
-            1.0,
-        );
+        let position: Point3<f32> = self.isometry.translation.vector.into();
+        let direction = target - position;
+        if direction == Vector3::zeros() {
+            return;
+        }
+
+        // looking straight up or down, any other up vector will do
+        let up = if direction.cross(&Vector3::y()) == Vector3::zeros() {
+            Vector3::z()
+        } else {
+            Vector3::y()
+        };
 
-        self.similarity = view;
+        self.isometry.rotation = UnitQuaternion::face_towards(&direction, &up);
         self.update_model_matrix();
     }
 
     pub fn set_scale(&mut self, s: f32) {
-        if s == 0.0 {
+        self.set_scale_xyz(s, s, s);
+    }
+
+    /// Scales the mesh along its own axes, before it is rotated.
+    pub fn set_scale_xyz(&mut self, x: f32, y: f32, z: f32) {
+        if x == 0.0 || y == 0.0 || z == 0.0 {
             return;
         }
-        self.similarity.set_scaling(s);
+        self.scale = Vector3::new(x, y, z);
         self.update_model_matrix();
     }
 
+    /// Uses `matrix` as the model matrix instead of the position, attitude and scale, until one of
+    /// them is set again.
+    pub fn set_model_matrix(&mut self, matrix: Matrix4<f32>) {
+        self.model_matrix = matrix;
+    }
+
+    pub fn normal_matrix(&self) -> Matrix3<f32> {
+        normal_matrix(&self.model_matrix)
+    }
+
+    pub fn world_bounding_sphere(&self) -> BoundingSphere {
+        self.bounding_sphere.transformed(&self.model_matrix)
+    }
+
     fn update_model_matrix(&mut self) {
-        self.model_matrix = self.similarity.to_homogeneous();
+        self.model_matrix =
+            self.isometry.to_homogeneous() * Matrix4::new_nonuniform_scaling(&self.scale);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // two faces folded at a right angle along the edge 0-1, and one face not touching them
+    const VERTICES: [[f32; 3]; 7] = [
+        [0.0, 0.0, 0.0],
+        [1.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0],
+        [0.0, 0.0, 1.0],
+        [5.0, 0.0, 0.0],
+        [6.0, 0.0, 0.0],
+        [5.0, 1.0, 0.0],
+    ];
+    const FACES: [[usize; 3]; 3] = [[0, 1, 2], [1, 0, 3], [4, 5, 6]];
+
+    fn vertex_normals(crease_angle: f32) -> [[[f32; 3]; 3]; 3] {
+        let mut adjacency = [0; 9];
+        let mut normals = [[[0.0; 3]; 3]; 3];
+        Geometry::compute_vertex_normals(
+            &VERTICES,
+            &FACES,
+            crease_angle,
+            &mut adjacency,
+            &mut normals,
+        )
+        .unwrap();
+        normals
+    }
+
+    #[test]
+    fn vertex_normals_keep_creases() {
+        let normals = vertex_normals(40f32.to_radians());
+
+        assert_eq!(normals[0], [[0.0, 0.0, 1.0]; 3]);
+        assert_eq!(normals[1], [[0.0, 1.0, 0.0]; 3]);
+        assert_eq!(normals[2], [[0.0, 0.0, 1.0]; 3]);
+    }
+
+    #[test]
+    fn vertex_normals_smooth_shared_vertices() {
+        let normals = vertex_normals(core::f32::consts::PI);
+        let half = core::f32::consts::FRAC_1_SQRT_2;
+
+        // the shared edge is averaged, the other corners only see their own face
+        assert_eq!(
+            normals[0],
+            [[0.0, half, half], [0.0, half, half], [0.0, 0.0, 1.0]]
+        );
+        assert_eq!(
+            normals[1],
+            [[0.0, half, half], [0.0, half, half], [0.0, 1.0, 0.0]]
+        );
+        assert_eq!(normals[2], [[0.0, 0.0, 1.0]; 3]);
+    }
+
+    #[test]
+    fn set_target_points_local_z_at_the_target() {
+        let mut mesh = K3dMesh::new(Geometry {
+            vertices: &VERTICES,
+            faces: &FACES,
+            ..Default::default()
+        });
+        mesh.set_position(1.0, 2.0, 3.0);
+        let position = Point3::new(1.0, 2.0, 3.0);
+
+        for target in [
+            Point3::new(2.0, 2.0, 3.0),
+            Point3::new(0.0, 2.0, 3.0),
+            Point3::new(1.0, 2.0, 4.0),
+            Point3::new(1.0, 2.0, -5.0),
+            Point3::new(-3.0, 7.0, 0.5),
+            Point3::new(1.0, 9.0, 3.0),
+            Point3::new(1.0, -1.0, 3.0),
+        ] {
+            mesh.set_target(target);
+
+            let z = mesh.model_matrix.transform_vector(&Vector3::z());
+            let expected = (target - position).normalize();
+            assert!((z - expected).norm() < 1e-5, "{target} gives {z}");
+            assert!((mesh.get_position() - position).norm() < 1e-5);
+        }
+
+        let rotation = mesh.isometry.rotation;
+        mesh.set_target(position);
+        assert_eq!(mesh.isometry.rotation, rotation);
+    }
+
+    #[test]
+    fn vertex_normals_need_room_for_every_corner() {
+        let mut normals = [[[0.0; 3]; 3]; 3];
+
+        assert_eq!(
+            Geometry::compute_vertex_normals(&VERTICES, &FACES, 0.0, &mut [0; 8], &mut normals),
+            Err(CapacityError)
+        );
     }
 }
@@ -0,0 +1,717 @@
+//! Generators for common shapes, centered on the origin with Y up. They write into a
+//! [`MeshBuilder`], either over caller-provided slices or over a [`MeshStorage`], and return the
+//! resulting [`Geometry`] with one normal per face and the edges as lines.
+
+use core::f32::consts::{PI, TAU};
+
+use nalgebra::Vector3;
+
+use super::Geometry;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CapacityError;
+
+/// Appends vertices, faces and lines to fixed buffers. Face normals are computed by
+/// [`MeshBuilder::build`] from the winding order.
+pub struct MeshBuilder<'b> {
+    vertices: &'b mut [[f32; 3]],
+    faces: &'b mut [[usize; 3]],
+    normals: &'b mut [[f32; 3]],
+    lines: &'b mut [[usize; 2]],
+    vertex_count: usize,
+    face_count: usize,
+    line_count: usize,
+}
+
+impl<'b> MeshBuilder<'b> {
+    /// `normals` and `lines` can be empty to generate geometry without them, otherwise `normals`
+    /// needs as many entries as there are faces.
+    pub fn new(
+        vertices: &'b mut [[f32; 3]],
+        faces: &'b mut [[usize; 3]],
+        normals: &'b mut [[f32; 3]],
+        lines: &'b mut [[usize; 2]],
+    ) -> MeshBuilder<'b> {
+        MeshBuilder {
+            vertices,
+            faces,
+            normals,
+            lines,
+            vertex_count: 0,
+            face_count: 0,
+            line_count: 0,
+        }
+    }
+
+    pub fn vertex(&mut self, vertex: [f32; 3]) -> Result<usize, CapacityError> {
+        *self
+            .vertices
+            .get_mut(self.vertex_count)
+            .ok_or(CapacityError)? = vertex;
+        self.vertex_count += 1;
+        Ok(self.vertex_count - 1)
+    }
+
+    /// Faces are counter-clockwise when seen from the outside.
+    pub fn face(&mut self, face: [usize; 3]) -> Result<(), CapacityError> {
+        if !self.normals.is_empty() && self.face_count >= self.normals.len() {
+            return Err(CapacityError);
+        }
+
+        *self.faces.get_mut(self.face_count).ok_or(CapacityError)? = face;
+        self.face_count += 1;
+        Ok(())
+    }
+
+    /// Two faces, and the four sides as lines.
+    pub fn quad(&mut self, quad: [usize; 4]) -> Result<(), CapacityError> {
+        let [a, b, c, d] = quad;
+        self.face([a, b, c])?;
+        self.face([a, c, d])?;
+
+        for line in [[a, b], [b, c], [c, d], [d, a]] {
+            self.line(line)?;
+        }
+        Ok(())
+    }
+
+    /// Lines already added, in either direction, are skipped.
+    pub fn line(&mut self, line: [usize; 2]) -> Result<(), CapacityError> {
+        if self.lines.is_empty() {
+            return Ok(());
+        }
+
+        let [a, b] = line;
+        if self.lines[..self.line_count]
+            .iter()
+            .any(|l| *l == [a, b] || *l == [b, a])
+        {
+            return Ok(());
+        }
+
+        *self.lines.get_mut(self.line_count).ok_or(CapacityError)? = line;
+        self.line_count += 1;
+        Ok(())
+    }
+
+    /// Adds the edges of all the faces as lines.
+    pub fn lines_from_faces(&mut self) -> Result<(), CapacityError> {
+        for i in 0..self.face_count {
+            let [a, b, c] = self.faces[i];
+            for line in [[a, b], [b, c], [c, a]] {
+                self.line(line)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn position(&self, index: usize) -> Vector3<f32> {
+        Vector3::from(self.vertices[index])
+    }
+
+    fn face_normal(&self, face: [usize; 3]) -> Vector3<f32> {
+        let [a, b, c] = face.map(|i| self.position(i));
+        (b - a).cross(&(c - a))
+    }
+
+    /// Like [`MeshBuilder::face`], but the winding is reversed if needed for the face to point
+    /// towards `outward`.
+    fn face_towards(
+        &mut self,
+        face: [usize; 3],
+        outward: Vector3<f32>,
+    ) -> Result<(), CapacityError> {
+        let [a, b, c] = face;
+
+        if self.face_normal(face).dot(&outward) < 0.0 {
+            self.face([a, c, b])
+        } else {
+            self.face(face)
+        }
+    }
+
+    fn quad_towards(
+        &mut self,
+        quad: [usize; 4],
+        outward: Vector3<f32>,
+    ) -> Result<(), CapacityError> {
+        let [a, b, c, d] = quad;
+
+        if self.face_normal([a, b, c]).dot(&outward) < 0.0 {
+            self.quad([d, c, b, a])
+        } else {
+            self.quad(quad)
+        }
+    }
+
+    pub fn build(self) -> Geometry<'b> {
+        let face_count = self.face_count;
+        let normal_count = if self.normals.is_empty() {
+            0
+        } else {
+            face_count
+        };
+
+        // cannot fail, both slices have the same length
+        Geometry::compute_face_normals(
+            self.vertices,
+            &self.faces[..normal_count],
+            &mut self.normals[..normal_count],
+        )
+        .ok();
+
+        let vertices: &'b [[f32; 3]] = self.vertices;
+        let faces: &'b [[usize; 3]] = self.faces;
+        let normals: &'b [[f32; 3]] = self.normals;
+        let lines: &'b [[usize; 2]] = self.lines;
+
+        Geometry {
+            vertices: &vertices[..self.vertex_count],
+            faces: &faces[..face_count],
+            normals: &normals[..normal_count],
+            lines: &lines[..self.line_count],
+            ..Default::default()
+        }
+    }
+}
+
+/// Buffers for up to `V` vertices, `F` faces and `L` lines, can be placed in a `static`.
+pub struct MeshStorage<const V: usize, const F: usize, const L: usize> {
+    pub vertices: [[f32; 3]; V],
+    pub faces: [[usize; 3]; F],
+    pub normals: [[f32; 3]; F],
+    pub lines: [[usize; 2]; L],
+}
+
+impl<const V: usize, const F: usize, const L: usize> Default for MeshStorage<V, F, L> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const V: usize, const F: usize, const L: usize> MeshStorage<V, F, L> {
+    pub const fn new() -> MeshStorage<V, F, L> {
+        MeshStorage {
+            vertices: [[0.0; 3]; V],
+            faces: [[0; 3]; F],
+            normals: [[0.0; 3]; F],
+            lines: [[0; 2]; L],
+        }
+    }
+
+    pub fn builder(&mut self) -> MeshBuilder<'_> {
+        MeshBuilder::new(
+            &mut self.vertices,
+            &mut self.faces,
+            &mut self.normals,
+            &mut self.lines,
+        )
+    }
+}
+
+/// Point at `angle` on a circle of the XZ plane.
+fn circle(radius: f32, angle: f32, y: f32) -> [f32; 3] {
+    let (sin, cos) = angle.sin_cos();
+    [radius * cos, y, radius * sin]
+}
+
+fn centroid(builder: &MeshBuilder, indices: &[usize]) -> Vector3<f32> {
+    indices
+        .iter()
+        .map(|i| builder.position(*i))
+        .sum::<Vector3<f32>>()
+        / indices.len() as f32
+}
+
+/// 8 vertices, 12 faces, 12 lines.
+pub fn cube(mut builder: MeshBuilder, size: f32) -> Result<Geometry, CapacityError> {
+    let h = size / 2.0;
+
+    // bit 0 is x, bit 1 is y, bit 2 is z
+    for i in 0..8 {
+        builder.vertex([
+            if i & 1 == 0 { -h } else { h },
+            if i & 2 == 0 { -h } else { h },
+            if i & 4 == 0 { -h } else { h },
+        ])?;
+    }
+
+    for axis in 0..3 {
+        let (u, v) = (1 << ((axis + 1) % 3), 1 << ((axis + 2) % 3));
+
+        for side in [0, 1 << axis] {
+            let quad = [side, side | u, side | u | v, side | v];
+            let outward = centroid(&builder, &quad);
+            builder.quad_towards(quad, outward)?;
+        }
+    }
+
+    Ok(builder.build())
+}
+
+/// `segments * (rings - 1) + 2` vertices, `2 * segments * (rings - 1)` faces and
+/// `segments * (2 * rings - 1)` lines.
+pub fn uv_sphere(
+    mut builder: MeshBuilder,
+    radius: f32,
+    segments: usize,
+    rings: usize,
+) -> Result<Geometry, CapacityError> {
+    let (segments, rings) = (segments.max(3), rings.max(2));
+
+    let top = builder.vertex([0.0, radius, 0.0])?;
+    for ring in 1..rings {
+        let (sin, cos) = (PI * ring as f32 / rings as f32).sin_cos();
+        for segment in 0..segments {
+            builder.vertex(circle(
+                radius * sin,
+                TAU * segment as f32 / segments as f32,
+                radius * cos,
+            ))?;
+        }
+    }
+    let bottom = builder.vertex([0.0, -radius, 0.0])?;
+
+    let at = |ring: usize, segment: usize| 1 + (ring - 1) * segments + segment % segments;
+
+    for segment in 0..segments {
+        let face = [top, at(1, segment), at(1, segment + 1)];
+        builder.face_towards(face, centroid(&builder, &face))?;
+        builder.line([top, at(1, segment)])?;
+
+        for ring in 1..rings - 1 {
+            let quad = [
+                at(ring, segment),
+                at(ring, segment + 1),
+                at(ring + 1, segment + 1),
+                at(ring + 1, segment),
+            ];
+            builder.quad_towards(quad, centroid(&builder, &quad))?;
+        }
+
+        let face = [bottom, at(rings - 1, segment), at(rings - 1, segment + 1)];
+        builder.face_towards(face, centroid(&builder, &face))?;
+        builder.line([at(rings - 1, segment), at(rings - 1, segment + 1)])?;
+        builder.line([bottom, at(rings - 1, segment)])?;
+    }
+
+    Ok(builder.build())
+}
+
+/// Subdivided icosahedron, `10 * 4^subdivisions + 2` vertices, `20 * 4^subdivisions` faces and
+/// `30 * 4^subdivisions` lines.
+pub fn icosphere(
+    mut builder: MeshBuilder,
+    radius: f32,
+    subdivisions: u32,
+) -> Result<Geometry, CapacityError> {
+    let t = (1.0 + 5.0f32.sqrt()) / 2.0;
+    let corners = [
+        [-1.0, t, 0.0],
+        [1.0, t, 0.0],
+        [-1.0, -t, 0.0],
+        [1.0, -t, 0.0],
+        [0.0, -1.0, t],
+        [0.0, 1.0, t],
+        [0.0, -1.0, -t],
+        [0.0, 1.0, -t],
+        [t, 0.0, -1.0],
+        [t, 0.0, 1.0],
+        [-t, 0.0, -1.0],
+        [-t, 0.0, 1.0],
+    ];
+    for corner in corners {
+        builder.vertex((Vector3::from(corner).normalize() * radius).into())?;
+    }
+
+    const FACES: [[usize; 3]; 20] = [
+        [0, 11, 5],
+        [0, 5, 1],
+        [0, 1, 7],
+        [0, 7, 10],
+        [0, 10, 11],
+        [1, 5, 9],
+        [5, 11, 4],
+        [11, 10, 2],
+        [10, 7, 6],
+        [7, 1, 8],
+        [3, 9, 4],
+        [3, 4, 2],
+        [3, 2, 6],
+        [3, 6, 8],
+        [3, 8, 9],
+        [4, 9, 5],
+        [2, 4, 11],
+        [6, 2, 10],
+        [8, 6, 7],
+        [9, 8, 1],
+    ];
+    for face in FACES {
+        builder.face(face)?;
+    }
+
+    for _ in 0..subdivisions {
+        // every face is replaced by its middle triangle, the corner ones are appended
+        for i in 0..builder.face_count {
+            let [a, b, c] = builder.faces[i];
+            let [ab, bc, ca] = [
+                midpoint(&mut builder, a, b, radius)?,
+                midpoint(&mut builder, b, c, radius)?,
+                midpoint(&mut builder, c, a, radius)?,
+            ];
+
+            builder.faces[i] = [ab, bc, ca];
+            builder.face([a, ab, ca])?;
+            builder.face([b, bc, ab])?;
+            builder.face([c, ca, bc])?;
+        }
+    }
+
+    builder.lines_from_faces()?;
+    Ok(builder.build())
+}
+
+/// Vertex halfway between `a` and `b` pushed out to the sphere, shared by the two faces of the
+/// edge.
+fn midpoint(
+    builder: &mut MeshBuilder,
+    a: usize,
+    b: usize,
+    radius: f32,
+) -> Result<usize, CapacityError> {
+    // the sum is commutative so both faces compute exactly the same position
+    let vertex: [f32; 3] =
+        ((builder.position(a) + builder.position(b)).normalize() * radius).into();
+
+    match builder.vertices[..builder.vertex_count]
+        .iter()
+        .position(|v| *v == vertex)
+    {
+        Some(index) => Ok(index),
+        None => builder.vertex(vertex),
+    }
+}
+
+/// Closed cylinder along Y, `2 * segments + 2` vertices, `4 * segments` faces and `3 * segments`
+/// lines.
+pub fn cylinder(
+    mut builder: MeshBuilder,
+    radius: f32,
+    height: f32,
+    segments: usize,
+) -> Result<Geometry, CapacityError> {
+    let segments = segments.max(3);
+    let h = height / 2.0;
+
+    for y in [h, -h] {
+        for segment in 0..segments {
+            builder.vertex(circle(radius, TAU * segment as f32 / segments as f32, y))?;
+        }
+    }
+    let top = builder.vertex([0.0, h, 0.0])?;
+    let bottom = builder.vertex([0.0, -h, 0.0])?;
+
+    for segment in 0..segments {
+        let next = (segment + 1) % segments;
+
+        let quad = [segment, next, segments + next, segments + segment];
+        let side = centroid(&builder, &quad);
+        builder.quad_towards(quad, Vector3::new(side.x, 0.0, side.z))?;
+
+        builder.face_towards([top, segment, next], Vector3::y())?;
+        builder.face_towards([bottom, segments + segment, segments + next], -Vector3::y())?;
+    }
+
+    Ok(builder.build())
+}
+
+/// Cone along Y with the apex on top, `segments + 2` vertices, `2 * segments` faces and
+/// `2 * segments` lines.
+pub fn cone(
+    mut builder: MeshBuilder,
+    radius: f32,
+    height: f32,
+    segments: usize,
+) -> Result<Geometry, CapacityError> {
+    let segments = segments.max(3);
+    let h = height / 2.0;
+
+    for segment in 0..segments {
+        builder.vertex(circle(radius, TAU * segment as f32 / segments as f32, -h))?;
+    }
+    let apex = builder.vertex([0.0, h, 0.0])?;
+    let bottom = builder.vertex([0.0, -h, 0.0])?;
+
+    for segment in 0..segments {
+        let next = (segment + 1) % segments;
+
+        let face = [apex, segment, next];
+        let side = centroid(&builder, &face);
+        builder.face_towards(face, Vector3::new(side.x, 0.0, side.z))?;
+        builder.face_towards([bottom, segment, next], -Vector3::y())?;
+
+        builder.line([segment, next])?;
+        builder.line([apex, segment])?;
+    }
+
+    Ok(builder.build())
+}
+
+/// Torus around Y, `segments * sides` vertices, `2 * segments * sides` faces and
+/// `2 * segments * sides` lines.
+pub fn torus(
+    mut builder: MeshBuilder,
+    radius: f32,
+    tube_radius: f32,
+    segments: usize,
+    sides: usize,
+) -> Result<Geometry, CapacityError> {
+    let (segments, sides) = (segments.max(3), sides.max(3));
+
+    for segment in 0..segments {
+        let angle = TAU * segment as f32 / segments as f32;
+        for side in 0..sides {
+            let (sin, cos) = (TAU * side as f32 / sides as f32).sin_cos();
+            builder.vertex(circle(radius + tube_radius * cos, angle, tube_radius * sin))?;
+        }
+    }
+
+    let at = |segment: usize, side: usize| (segment % segments) * sides + side % sides;
+
+    for segment in 0..segments {
+        for side in 0..sides {
+            let quad = [
+                at(segment, side),
+                at(segment + 1, side),
+                at(segment + 1, side + 1),
+                at(segment, side + 1),
+            ];
+
+            // away from the center of the tube
+            let center = centroid(&builder, &quad);
+            let axis = Vector3::new(center.x, 0.0, center.z).normalize() * radius;
+            builder.quad_towards(quad, center - axis)?;
+        }
+    }
+
+    Ok(builder.build())
+}
+
+/// Grid on the XZ plane facing up, `(columns + 1) * (rows + 1)` vertices, `2 * columns * rows`
+/// faces and `columns * (rows + 1) + rows * (columns + 1)` lines.
+pub fn plane(
+    mut builder: MeshBuilder,
+    width: f32,
+    depth: f32,
+    columns: usize,
+    rows: usize,
+) -> Result<Geometry, CapacityError> {
+    let (columns, rows) = (columns.max(1), rows.max(1));
+
+    for row in 0..=rows {
+        for column in 0..=columns {
+            builder.vertex([
+                width * (column as f32 / columns as f32 - 0.5),
+                0.0,
+                depth * (row as f32 / rows as f32 - 0.5),
+            ])?;
+        }
+    }
+
+    let at = |column: usize, row: usize| row * (columns + 1) + column;
+
+    for row in 0..rows {
+        for column in 0..columns {
+            let quad = [
+                at(column, row),
+                at(column + 1, row),
+                at(column + 1, row + 1),
+                at(column, row + 1),
+            ];
+            builder.quad_towards(quad, Vector3::y())?;
+        }
+    }
+
+    Ok(builder.build())
+}
+
+/// X, Y and Z axes as arrows of the given length, only lines: 10 vertices, 9 lines.
+pub fn axis_gizmo(mut builder: MeshBuilder, length: f32) -> Result<Geometry, CapacityError> {
+    let origin = builder.vertex([0.0; 3])?;
+    let head = length * 0.1;
+
+    for axis in 0..3 {
+        let (u, v) = (axis, (axis + 1) % 3);
+
+        let mut tip = [0.0; 3];
+        tip[u] = length;
+        let tip = builder.vertex(tip)?;
+        builder.line([origin, tip])?;
+
+        for sign in [1.0, -1.0] {
+            let mut barb = [0.0; 3];
+            barb[u] = length - head;
+            barb[v] = sign * head / 2.0;
+            let barb = builder.vertex(barb)?;
+            builder.line([tip, barb])?;
+        }
+    }
+
+    Ok(builder.build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counts(geometry: &Geometry) -> (usize, usize, usize) {
+        (
+            geometry.vertices.len(),
+            geometry.faces.len(),
+            geometry.lines.len(),
+        )
+    }
+
+    /// Checks that every face points away from `inside(centroid)`, both by its winding and by its
+    /// stored normal.
+    fn assert_outward(geometry: &Geometry, inside: impl Fn(Vector3<f32>) -> Vector3<f32>) {
+        assert!(geometry.check_validity());
+        assert_eq!(geometry.normals.len(), geometry.faces.len());
+
+        for (face, normal) in geometry.faces.iter().zip(geometry.normals) {
+            let [a, b, c] = face.map(|i| Vector3::from(geometry.vertices[i]));
+            let winding = (b - a).cross(&(c - a));
+            let centroid = (a + b + c) / 3.0;
+            let outward = centroid - inside(centroid);
+
+            assert!(winding.dot(&outward) > 0.0, "{face:?} is wound inward");
+            assert!(
+                Vector3::from(*normal).dot(&outward) > 0.0,
+                "{face:?} has an inward normal"
+            );
+        }
+    }
+
+    fn origin(_: Vector3<f32>) -> Vector3<f32> {
+        Vector3::zeros()
+    }
+
+    #[test]
+    fn cube() {
+        let mut storage = MeshStorage::<8, 12, 12>::new();
+        let geometry = super::cube(storage.builder(), 2.0).unwrap();
+
+        assert_eq!(counts(&geometry), (8, 12, 12));
+        assert_outward(&geometry, origin);
+    }
+
+    #[test]
+    fn uv_sphere() {
+        let (segments, rings) = (7, 5);
+        let mut storage = MeshStorage::<30, 56, 63>::new();
+        let geometry = super::uv_sphere(storage.builder(), 1.5, segments, rings).unwrap();
+
+        assert_eq!(
+            counts(&geometry),
+            (
+                segments * (rings - 1) + 2,
+                2 * segments * (rings - 1),
+                segments * (2 * rings - 1)
+            )
+        );
+        assert_outward(&geometry, origin);
+    }
+
+    #[test]
+    fn icosphere() {
+        for subdivisions in 0..3 {
+            let mut storage = MeshStorage::<162, 320, 480>::new();
+            let geometry = super::icosphere(storage.builder(), 1.0, subdivisions).unwrap();
+
+            let scale = 4usize.pow(subdivisions);
+            assert_eq!(counts(&geometry), (10 * scale + 2, 20 * scale, 30 * scale));
+            assert_outward(&geometry, origin);
+
+            for vertex in geometry.vertices {
+                assert!((Vector3::from(*vertex).norm() - 1.0).abs() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn cylinder() {
+        let segments = 6;
+        let mut storage = MeshStorage::<14, 24, 18>::new();
+        let geometry = super::cylinder(storage.builder(), 1.0, 3.0, segments).unwrap();
+
+        assert_eq!(
+            counts(&geometry),
+            (2 * segments + 2, 4 * segments, 3 * segments)
+        );
+        assert_outward(&geometry, origin);
+    }
+
+    #[test]
+    fn cone() {
+        let segments = 5;
+        let mut storage = MeshStorage::<7, 10, 10>::new();
+        let geometry = super::cone(storage.builder(), 1.0, 2.0, segments).unwrap();
+
+        assert_eq!(
+            counts(&geometry),
+            (segments + 2, 2 * segments, 2 * segments)
+        );
+        assert_outward(&geometry, origin);
+    }
+
+    #[test]
+    fn torus() {
+        let (radius, segments, sides) = (2.0, 8, 5);
+        let mut storage = MeshStorage::<40, 80, 80>::new();
+        let geometry = super::torus(storage.builder(), radius, 0.5, segments, sides).unwrap();
+
+        assert_eq!(
+            counts(&geometry),
+            (segments * sides, 2 * segments * sides, 2 * segments * sides)
+        );
+        // the torus is not convex, faces point away from the center of the tube
+        assert_outward(&geometry, |centroid| {
+            Vector3::new(centroid.x, 0.0, centroid.z).normalize() * radius
+        });
+    }
+
+    #[test]
+    fn overflow_is_an_error() {
+        assert_eq!(
+            super::cube(MeshStorage::<7, 12, 12>::new().builder(), 1.0).err(),
+            Some(CapacityError)
+        );
+        assert_eq!(
+            super::cube(MeshStorage::<8, 11, 12>::new().builder(), 1.0).err(),
+            Some(CapacityError)
+        );
+        assert_eq!(
+            super::cube(MeshStorage::<8, 12, 11>::new().builder(), 1.0).err(),
+            Some(CapacityError)
+        );
+        assert_eq!(
+            super::icosphere(MeshStorage::<41, 80, 120>::new().builder(), 1.0, 1).err(),
+            Some(CapacityError)
+        );
+        assert_eq!(
+            super::torus(MeshStorage::<40, 80, 79>::new().builder(), 2.0, 0.5, 8, 5).err(),
+            Some(CapacityError)
+        );
+    }
+
+    #[test]
+    fn geometry_without_normals_or_lines() {
+        let mut vertices = [[0.0; 3]; 8];
+        let mut faces = [[0; 3]; 12];
+        let builder = MeshBuilder::new(&mut vertices, &mut faces, &mut [], &mut []);
+        let geometry = super::cube(builder, 1.0).unwrap();
+
+        assert_eq!(counts(&geometry), (8, 12, 0));
+        assert!(geometry.normals.is_empty());
+    }
+}
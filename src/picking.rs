@@ -0,0 +1,231 @@
+use nalgebra::{Point3, Vector3};
+
+use crate::mesh::{BoundingSphere, K3dMesh};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ray {
+    pub origin: Point3<f32>,
+    pub direction: Vector3<f32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PickHit {
+    pub mesh_index: usize,
+    pub face_index: usize,
+    pub barycentric: [f32; 3],
+    /// Along the ray from its origin, which is on the near plane rather than at the camera for
+    /// rays from [`crate::K3dengine::screen_to_ray`] and [`crate::camera::Camera::unproject`].
+    pub distance: f32,
+    pub point: Point3<f32>,
+}
+
+impl Ray {
+    pub fn new(origin: Point3<f32>, direction: Vector3<f32>) -> Ray {
+        Ray {
+            origin,
+            direction: direction
+                .try_normalize(f32::EPSILON)
+                .unwrap_or(-Vector3::z()),
+        }
+    }
+
+    pub fn at(&self, distance: f32) -> Point3<f32> {
+        self.origin + self.direction * distance
+    }
+
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        let to_center = sphere.center - self.origin;
+        let along = to_center.dot(&self.direction);
+        let distance_sq = to_center.norm_squared() - along * along;
+        let radius_sq = sphere.radius * sphere.radius;
+
+        if distance_sq > radius_sq {
+            return false;
+        }
+
+        // reject spheres entirely behind the origin
+        along >= 0.0 || to_center.norm_squared() <= radius_sq
+    }
+
+    /// Möller–Trumbore intersection, both windings are hit.
+    /// Returns the distance along the ray and the barycentric coordinates of the hit point.
+    pub fn intersect_triangle(&self, triangle: [Point3<f32>; 3]) -> Option<(f32, [f32; 3])> {
+        let [a, b, c] = triangle;
+        let edge1 = b - a;
+        let edge2 = c - a;
+
+        let p = self.direction.cross(&edge2);
+        let det = edge1.dot(&p);
+        // relative to the edge lengths so that small or distant triangles are not taken as parallel
+        if det.abs() <= f32::EPSILON * edge1.norm() * edge2.norm() {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+
+        let t_vec = self.origin - a;
+        let u = t_vec.dot(&p) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+
+        let q = t_vec.cross(&edge1);
+        let v = self.direction.dot(&q) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let distance = edge2.dot(&q) * inv_det;
+        if distance < 0.0 {
+            return None;
+        }
+
+        Some((distance, [1.0 - u - v, u, v]))
+    }
+
+    /// Returns the nearest face of the mesh hit by the ray, as (face index, distance, barycentric).
+    pub fn intersect_mesh(
+        &self,
+        mesh: &K3dMesh,
+        use_bounding_sphere: bool,
+    ) -> Option<(usize, f32, [f32; 3])> {
+        if use_bounding_sphere && !self.intersects_sphere(&mesh.world_bounding_sphere()) {
+            return None;
+        }
+
+        let vertices = mesh.geometry.vertices;
+        let to_world = |i: usize| {
+            mesh.model_matrix.transform_point(&Point3::new(
+                vertices[i][0],
+                vertices[i][1],
+                vertices[i][2],
+            ))
+        };
+
+        let mut nearest: Option<(usize, f32, [f32; 3])> = None;
+        for (face_index, face) in mesh.geometry.faces.iter().enumerate() {
+            let triangle = [to_world(face[0]), to_world(face[1]), to_world(face[2])];
+
+            if let Some((distance, barycentric)) = self.intersect_triangle(triangle)
+                && nearest.is_none_or(|(_, d, _)| distance < d)
+            {
+                nearest = Some((face_index, distance, barycentric));
+            }
+        }
+
+        nearest
+    }
+
+    /// Returns the nearest face hit among `meshes`, skipping the meshes whose bounding sphere is
+    /// missed, see [`Ray::pick_with`].
+    pub fn pick<'a, MS>(&self, meshes: MS) -> Option<PickHit>
+    where
+        MS: IntoIterator<Item = &'a K3dMesh<'a>>,
+    {
+        self.pick_with(meshes, true)
+    }
+
+    /// Like [`Ray::pick`], testing every face of every mesh when `use_bounding_sphere` is false.
+    pub fn pick_with<'a, MS>(&self, meshes: MS, use_bounding_sphere: bool) -> Option<PickHit>
+    where
+        MS: IntoIterator<Item = &'a K3dMesh<'a>>,
+    {
+        let mut nearest: Option<PickHit> = None;
+
+        for (mesh_index, mesh) in meshes.into_iter().enumerate() {
+            if let Some((face_index, distance, barycentric)) =
+                self.intersect_mesh(mesh, use_bounding_sphere)
+                && nearest.is_none_or(|hit| distance < hit.distance)
+            {
+                nearest = Some(PickHit {
+                    mesh_index,
+                    face_index,
+                    barycentric,
+                    distance,
+                    point: self.at(distance),
+                });
+            }
+        }
+
+        nearest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::Geometry;
+
+    const VERTICES: [[f32; 3]; 3] = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+
+    fn triangle(scale: f32) -> [Point3<f32>; 3] {
+        VERTICES.map(|v| Point3::from(v) * scale)
+    }
+
+    fn assert_close(a: &[f32], b: &[f32]) {
+        for (a, b) in a.iter().zip(b) {
+            assert!((a - b).abs() < 1e-5, "{a:?} != {b:?}");
+        }
+    }
+
+    #[test]
+    fn hit_gives_distance_and_barycentric_coordinates() {
+        let ray = Ray::new(Point3::new(0.25, 0.5, 5.0), -Vector3::z());
+
+        let (distance, barycentric) = ray.intersect_triangle(triangle(1.0)).unwrap();
+
+        assert_close(&[distance], &[5.0]);
+        assert_close(&barycentric, &[0.25, 0.25, 0.5]);
+        assert_close(ray.at(distance).coords.as_slice(), &[0.25, 0.5, 0.0]);
+    }
+
+    #[test]
+    fn misses_outside_and_behind() {
+        let outside = Ray::new(Point3::new(0.75, 0.5, 5.0), -Vector3::z());
+        let behind = Ray::new(Point3::new(0.25, 0.25, 5.0), Vector3::z());
+
+        assert_eq!(outside.intersect_triangle(triangle(1.0)), None);
+        assert_eq!(behind.intersect_triangle(triangle(1.0)), None);
+    }
+
+    #[test]
+    fn parallel_rays_miss() {
+        let in_plane = Ray::new(Point3::new(-1.0, 0.25, 0.0), Vector3::x());
+        let above = Ray::new(Point3::new(-1.0, 0.25, 1.0), Vector3::x());
+
+        assert_eq!(in_plane.intersect_triangle(triangle(1.0)), None);
+        assert_eq!(above.intersect_triangle(triangle(1.0)), None);
+    }
+
+    #[test]
+    fn small_triangles_are_hit() {
+        let ray = Ray::new(Point3::new(0.25e-4, 0.25e-4, 5.0), -Vector3::z());
+
+        let (distance, barycentric) = ray.intersect_triangle(triangle(1e-4)).unwrap();
+
+        assert_close(&[distance], &[5.0]);
+        assert_close(&barycentric, &[0.5, 0.25, 0.25]);
+    }
+
+    #[test]
+    fn pick_returns_the_nearest_mesh() {
+        let geometry = || Geometry {
+            vertices: &VERTICES,
+            faces: &[[0, 1, 2]],
+            ..Default::default()
+        };
+        let mut far = K3dMesh::new(geometry());
+        far.set_position(0.0, 0.0, -1.0);
+        let near = K3dMesh::new(geometry());
+
+        let ray = Ray::new(Point3::new(0.25, 0.25, 5.0), -Vector3::z());
+        for hit in [ray.pick([&far, &near]), ray.pick_with([&far, &near], false)] {
+            let hit = hit.unwrap();
+            assert_eq!((hit.mesh_index, hit.face_index), (1, 0));
+            assert_close(&[hit.distance], &[5.0]);
+            assert_close(hit.point.coords.as_slice(), &[0.25, 0.25, 0.0]);
+        }
+
+        let miss = Ray::new(Point3::new(2.0, 2.0, 5.0), -Vector3::z());
+        assert_eq!(miss.pick([&far, &near]), None);
+    }
+}
@@ -0,0 +1,122 @@
+use heapless::Vec;
+use nalgebra::Matrix4;
+
+use crate::mesh::K3dMesh;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeId(usize);
+
+pub struct Node<'a> {
+    local: Matrix4<f32>,
+    world: Matrix4<f32>,
+    parent: Option<NodeId>,
+    pub mesh: Option<&'a K3dMesh<'a>>,
+}
+
+impl Node<'_> {
+    /// Transform relative to the parent node.
+    pub fn local_matrix(&self) -> &Matrix4<f32> {
+        &self.local
+    }
+
+    /// Transform relative to the scene, the local transforms of all the ancestors combined.
+    pub fn world_matrix(&self) -> &Matrix4<f32> {
+        &self.world
+    }
+
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+}
+
+/// Hierarchy of up to `N` nodes, each one positioned relative to its parent. The model matrix of
+/// the mesh of a node is applied before the node world transform.
+pub struct Scene<'a, const N: usize> {
+    // parents always come before their children
+    nodes: Vec<Node<'a>, N>,
+}
+
+impl<'a, const N: usize> Default for Scene<'a, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, const N: usize> Scene<'a, N> {
+    pub fn new() -> Scene<'a, N> {
+        Scene { nodes: Vec::new() }
+    }
+
+    /// Returns `None` if the scene is full or `parent` is not part of it.
+    pub fn add_node(
+        &mut self,
+        parent: Option<NodeId>,
+        local: Matrix4<f32>,
+        mesh: Option<&'a K3dMesh<'a>>,
+    ) -> Option<NodeId> {
+        let world = match parent {
+            Some(parent) => self.nodes.get(parent.0)?.world * local,
+            None => local,
+        };
+
+        self.nodes
+            .push(Node {
+                local,
+                world,
+                parent,
+                mesh,
+            })
+            .ok()?;
+
+        Some(NodeId(self.nodes.len() - 1))
+    }
+
+    pub fn node(&self, id: NodeId) -> Option<&Node<'a>> {
+        self.nodes.get(id.0)
+    }
+
+    pub fn set_mesh(&mut self, id: NodeId, mesh: Option<&'a K3dMesh<'a>>) {
+        if let Some(node) = self.nodes.get_mut(id.0) {
+            node.mesh = mesh;
+        }
+    }
+
+    /// Moves the node and all of its descendants.
+    pub fn set_local_matrix(&mut self, id: NodeId, local: Matrix4<f32>) {
+        let Some(node) = self.nodes.get_mut(id.0) else {
+            return;
+        };
+        node.local = local;
+
+        // descendants can only come after the node
+        for i in id.0..self.nodes.len() {
+            let world = match self.nodes[i].parent {
+                Some(parent) => self.nodes[parent.0].world * self.nodes[i].local,
+                None => self.nodes[i].local,
+            };
+            self.nodes[i].world = world;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = (NodeId, &Node<'a>)> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (NodeId(i), node))
+    }
+
+    /// Meshes of the scene along with their model matrix in scene space.
+    pub fn meshes(&self) -> impl Iterator<Item = (&'a K3dMesh<'a>, Matrix4<f32>)> {
+        self.nodes
+            .iter()
+            .filter_map(|node| node.mesh.map(|mesh| (mesh, node.world * mesh.model_matrix)))
+    }
+}
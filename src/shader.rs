@@ -0,0 +1,267 @@
+use core::fmt::Debug;
+
+use embedded_graphics_core::{
+    Pixel, draw_target::DrawTarget, geometry::Point, pixelcolor::Rgb565, pixelcolor::RgbColor,
+};
+use nalgebra::{Matrix3, Matrix4, Point3, SVector, Vector3, Vector4};
+
+use crate::K3dengine;
+use crate::SUBPIXEL_BITS;
+use crate::SubpixelPoint;
+use crate::Viewport;
+use crate::draw::Edges;
+#[cfg(feature = "fixed-point")]
+use crate::fixed::Fixed;
+use crate::mesh::K3dMesh;
+
+/// Per-vertex values interpolated across a face by the rasterizer.
+pub trait Varying: Copy {
+    /// Weighted sum of the values at the three corners, the weights add up to 1.
+    fn interpolate(values: [Self; 3], weights: [f32; 3]) -> Self;
+}
+
+impl Varying for () {
+    fn interpolate(_values: [(); 3], _weights: [f32; 3]) {}
+}
+
+impl Varying for f32 {
+    fn interpolate(values: [f32; 3], weights: [f32; 3]) -> f32 {
+        values[0] * weights[0] + values[1] * weights[1] + values[2] * weights[2]
+    }
+}
+
+impl<const N: usize> Varying for [f32; N] {
+    fn interpolate(values: [[f32; N]; 3], weights: [f32; 3]) -> [f32; N] {
+        core::array::from_fn(|i| {
+            f32::interpolate([values[0][i], values[1][i], values[2][i]], weights)
+        })
+    }
+}
+
+impl<const N: usize> Varying for SVector<f32, N> {
+    fn interpolate(values: [SVector<f32, N>; 3], weights: [f32; 3]) -> SVector<f32, N> {
+        values[0] * weights[0] + values[1] * weights[1] + values[2] * weights[2]
+    }
+}
+
+pub struct VertexInput<'a> {
+    pub mesh: &'a K3dMesh<'a>,
+    pub index: usize,
+    /// Position in model space.
+    pub position: Point3<f32>,
+    /// Normal of the face being drawn, if the geometry has normals.
+    pub normal: Option<Vector3<f32>>,
+    /// Normal of the vertex in the face being drawn, if the geometry has vertex normals.
+    pub vertex_normal: Option<Vector3<f32>>,
+    /// Vertex color, if the geometry has colors.
+    pub color: Option<Rgb565>,
+    /// Model-view-projection matrix of the mesh.
+    pub mvp: Matrix4<f32>,
+    /// Transforms model space normals to world space, see [`crate::mesh::normal_matrix`].
+    pub normal_matrix: Matrix3<f32>,
+}
+
+impl VertexInput<'_> {
+    /// The position transformed by the model-view-projection matrix, what a vertex shader
+    /// usually returns.
+    pub fn clip_position(&self) -> Vector4<f32> {
+        self.mvp * self.position.to_homogeneous()
+    }
+}
+
+pub trait VertexShader {
+    type Varying: Varying;
+
+    /// Returns the clip-space position of the vertex and the values to interpolate across the face.
+    fn vertex(&self, input: &VertexInput) -> (Vector4<f32>, Self::Varying);
+}
+
+/// A pixel covered by a face.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fragment {
+    pub x: i32,
+    pub y: i32,
+    /// 0 on the near plane, 1 on the far plane.
+    pub depth: f32,
+}
+
+pub trait FragmentShader<V: Varying> {
+    /// Returns the color of the fragment, or `None` to discard it.
+    fn fragment(&self, varying: &V, fragment: &Fragment) -> Option<Rgb565>;
+}
+
+/// Interpolates the vertex colors, or uses the mesh color for geometry without colors.
+pub struct VertexColor;
+
+impl VertexShader for VertexColor {
+    type Varying = Vector3<f32>;
+
+    fn vertex(&self, input: &VertexInput) -> (Vector4<f32>, Vector3<f32>) {
+        let color = input.color.unwrap_or(input.mesh.color);
+
+        (
+            input.clip_position(),
+            Vector3::new(color.r() as f32, color.g() as f32, color.b() as f32),
+        )
+    }
+}
+
+impl FragmentShader<Vector3<f32>> for VertexColor {
+    fn fragment(&self, color: &Vector3<f32>, _fragment: &Fragment) -> Option<Rgb565> {
+        Some(Rgb565::new(
+            (color.x + 0.5) as u8,
+            (color.y + 0.5) as u8,
+            (color.z + 0.5) as u8,
+        ))
+    }
+}
+
+/// A vertex after the perspective divide, in screen coordinates.
+pub(crate) struct ProjectedVertex<V> {
+    pub x: f32,
+    pub y: f32,
+    pub depth: f32,
+    /// Clip-space w, the divisor of the perspective divide.
+    pub w: f32,
+    pub varying: V,
+}
+
+fn subpixel<V>(vertex: &ProjectedVertex<V>) -> SubpixelPoint {
+    let scale = (1 << SUBPIXEL_BITS) as f32;
+    SubpixelPoint::new((vertex.x * scale) as i32, (vertex.y * scale) as i32)
+}
+
+/// Twice the signed area of the triangle in sub-pixels, negative when it is counter-clockwise as
+/// seen by the camera (screen y goes down).
+pub(crate) fn signed_area<V>(vertices: &[ProjectedVertex<V>; 3]) -> i64 {
+    K3dengine::signed_area(&vertices.each_ref().map(subpixel))
+}
+
+/// Depth and perspective-correct varying weights inside a triangle, from the values of its edge
+/// functions.
+#[cfg(not(feature = "fixed-point"))]
+struct Interpolation {
+    depth: [f32; 3],
+    inv_w: [f32; 3],
+    area: f32,
+}
+
+#[cfg(not(feature = "fixed-point"))]
+impl Interpolation {
+    fn new<V>(vertices: &[ProjectedVertex<V>; 3], area: i64) -> Interpolation {
+        Interpolation {
+            depth: vertices.each_ref().map(|v| v.depth),
+            inv_w: vertices.each_ref().map(|v| 1.0 / v.w),
+            area: area as f32,
+        }
+    }
+
+    fn at(&self, weights: &[i64; 3]) -> (f32, [f32; 3]) {
+        let weights = weights.map(|w| w as f32 / self.area);
+
+        let depth = (0..3).map(|i| weights[i] * self.depth[i]).sum();
+
+        // weights of the varyings, undoing the perspective divide
+        let perspective: [f32; 3] = core::array::from_fn(|i| weights[i] * self.inv_w[i]);
+        let sum: f32 = perspective.iter().sum();
+
+        (depth, perspective.map(|w| w / sum))
+    }
+}
+
+#[cfg(feature = "fixed-point")]
+struct Interpolation {
+    depth: [Fixed; 3],
+    /// In Q16.16, at least 1.
+    w: [i64; 3],
+    area: i64,
+    /// Low bits dropped from the edge functions of huge triangles, so that they can be turned
+    /// into Q16.16 without overflowing.
+    shift: u32,
+}
+
+#[cfg(feature = "fixed-point")]
+impl Interpolation {
+    fn new<V>(vertices: &[ProjectedVertex<V>; 3], area: i64) -> Interpolation {
+        let shift = (i64::BITS - area.leading_zeros()).saturating_sub(40);
+
+        Interpolation {
+            depth: vertices.each_ref().map(|v| Fixed::from_f32(v.depth)),
+            w: vertices
+                .each_ref()
+                .map(|v| (Fixed::from_f32(v.w).0 as i64).max(1)),
+            area: area >> shift,
+            shift,
+        }
+    }
+
+    /// Converts to `f32` at the end for [`Fragment`] and [`Varying`], with integer operations.
+    fn at(&self, weights: &[i64; 3]) -> (f32, [f32; 3]) {
+        let weights = weights.map(|w| ((w >> self.shift) << Fixed::FRAC_BITS) / self.area);
+
+        let depth = (0..3)
+            .map(|i| weights[i] * self.depth[i].0 as i64)
+            .sum::<i64>();
+        let depth = Fixed::saturate(depth >> Fixed::FRAC_BITS);
+
+        // weights of the varyings, undoing the perspective divide
+        let perspective: [i64; 3] = core::array::from_fn(|i| (weights[i] << 24) / self.w[i]);
+        let sum = perspective.iter().sum::<i64>().max(1);
+
+        (
+            depth.to_f32(),
+            perspective.map(|w| Fixed(((w << Fixed::FRAC_BITS) / sum) as i32).to_f32()),
+        )
+    }
+}
+
+/// Runs `fragment_shader` on the pixels of `viewport` whose center is inside the triangle, with
+/// perspective-correct varyings. Coverage follows the same rule as [`crate::draw`].
+pub(crate) fn rasterize<V, FS, D>(
+    vertices: &[ProjectedVertex<V>; 3],
+    viewport: &Viewport,
+    fragment_shader: &FS,
+    target: &mut D,
+) where
+    V: Varying,
+    FS: FragmentShader<V>,
+    D: DrawTarget,
+    <D as DrawTarget>::Error: Debug,
+    D::Color: From<Rgb565>,
+{
+    let points = vertices.each_ref().map(subpixel);
+    let Some(edges) = Edges::new(points) else {
+        return;
+    };
+    let interpolation = Interpolation::new(vertices, edges.area);
+
+    let [a, b, c] = points;
+    let min_x = (a.x.min(b.x).min(c.x) >> SUBPIXEL_BITS).max(viewport.x as i32);
+    let max_x = (a.x.max(b.x).max(c.x) + (1 << SUBPIXEL_BITS) - 1) >> SUBPIXEL_BITS;
+    let max_x = max_x.min(viewport.x as i32 + viewport.width as i32 - 1);
+    let min_y = (a.y.min(b.y).min(c.y) >> SUBPIXEL_BITS).max(viewport.y as i32);
+    let max_y = (a.y.max(b.y).max(c.y) + (1 << SUBPIXEL_BITS) - 1) >> SUBPIXEL_BITS;
+    let max_y = max_y.min(viewport.y as i32 + viewport.height as i32 - 1);
+
+    let half = 1 << (SUBPIXEL_BITS - 1);
+    let varyings = vertices.each_ref().map(|v| v.varying);
+    let pixels = (min_y..=max_y)
+        .flat_map(|y| (min_x..=max_x).map(move |x| (x, y)))
+        .filter_map(|(x, y)| {
+            let weights = edges.weights(
+                ((x as i64) << SUBPIXEL_BITS) + half,
+                ((y as i64) << SUBPIXEL_BITS) + half,
+            );
+            if !edges.contains(&weights) {
+                return None;
+            }
+
+            let (depth, weights) = interpolation.at(&weights);
+            let varying = V::interpolate(varyings, weights);
+
+            let color = fragment_shader.fragment(&varying, &Fragment { x, y, depth })?;
+            Some(Pixel(Point::new(x, y), color.into()))
+        });
+
+    target.draw_iter(pixels).unwrap();
+}